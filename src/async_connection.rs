@@ -0,0 +1,449 @@
+use std::io;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+
+use super::connection::{check_ok, check_reply};
+use super::error::{ClientError, ClientResult, ErrorCode, ServerError};
+use super::hello::hello_command;
+use super::ConnectionConfig;
+use super::RespParser;
+use super::RespType;
+use super::ServerInfo;
+use super::{Protocol, RespConfig};
+
+const READ_SIZE: usize = 16 * 1024;
+
+type ReplySender = oneshot::Sender<ClientResult<RespType>>;
+
+struct Request {
+    resp: RespType,
+    reply: ReplySender,
+}
+
+/// An async connection to a Redis server that pipelines requests, many
+/// commands can be in flight at once over the one connection.
+///
+/// The connection is driven by two background tasks, one writing queued
+/// requests in batches and one reading replies, which are matched to requests
+/// in the order they were sent. Clones share the same connection.
+#[derive(Clone)]
+pub struct AsyncConnection {
+    requests: mpsc::UnboundedSender<Request>,
+    config: ConnectionConfig,
+    server_info: Option<ServerInfo>,
+    /// The protocol the reader parses replies with, shared so `HELLO` can
+    /// switch it
+    protocol: Arc<AtomicU8>,
+}
+
+impl AsyncConnection {
+    /// Connects over TCP, then authenticates and selects the database as set
+    /// in the config
+    pub async fn connect_tcp<A: ToSocketAddrs>(
+        addr: A,
+        config: &ConnectionConfig,
+    ) -> ClientResult<Self> {
+        let stream = match config.connect_timeout {
+            None => TcpStream::connect(addr).await?,
+            Some(timeout) => tokio::time::timeout(timeout, TcpStream::connect(addr))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??,
+        };
+        stream.set_nodelay(true)?;
+        Self::setup(stream, config).await
+    }
+
+    /// Connects over a Unix domain socket, then authenticates and selects the
+    /// database as set in the config
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<Path>>(
+        path: P,
+        config: &ConnectionConfig,
+    ) -> ClientResult<Self> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Self::setup(stream, config).await
+    }
+
+    /// Starts the background tasks for an already connected stream, no setup
+    /// commands are sent. Must be called from within a tokio runtime.
+    pub fn new<S>(stream: S, config: &ConnectionConfig) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let (requests, requests_rx) = mpsc::unbounded_channel();
+        let (pending, pending_rx) = mpsc::unbounded_channel();
+        let protocol = Arc::new(AtomicU8::new(config.resp.protocol as u8));
+        let reader = tokio::spawn(read_replies(
+            reader,
+            pending_rx,
+            config.resp.clone(),
+            protocol.clone(),
+        ));
+        tokio::spawn(async move {
+            write_requests(writer, requests_rx, pending).await;
+            // Replies can not arrive for requests that failed to be written
+            reader.abort();
+        });
+        AsyncConnection {
+            requests,
+            config: config.clone(),
+            server_info: None,
+            protocol,
+        }
+    }
+
+    async fn setup<S>(stream: S, config: &ConnectionConfig) -> ClientResult<Self>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let mut connection = Self::new(stream, config);
+        if let Some(protocol) = config.protocol {
+            connection.server_info = Some(connection.hello(protocol, config).await?);
+        }
+        for command in config.setup_commands(config.protocol.is_some()) {
+            connection.command_ok(command).await?;
+        }
+        Ok(connection)
+    }
+
+    /// Sends `HELLO`, authenticating and setting the client name as set in
+    /// the config, and sets the parser up for the negotiated protocol. If the
+    /// server replies `NOPROTO`, RESP2 is asked for instead.
+    pub async fn hello(
+        &self,
+        protocol: Protocol,
+        config: &ConnectionConfig,
+    ) -> ClientResult<ServerInfo> {
+        // The reply to `HELLO 3` is a RESP3 map, even when RESP2 was in use.
+        // Switched before sending so the read with the reply sees it.
+        let previous = self.protocol.swap(Protocol::Resp3 as u8, Ordering::Relaxed);
+        let reply = match self.command(hello_command(protocol, config)).await {
+            Err(ClientError::ReplyError(ServerError {
+                code: ErrorCode::NoProto,
+                ..
+            })) if protocol != Protocol::Resp2 => {
+                self.command(hello_command(Protocol::Resp2, config)).await
+            }
+            result => result,
+        };
+        let info = match reply.and_then(ServerInfo::try_from) {
+            Ok(info) => info,
+            Err(error) => {
+                self.protocol.store(previous, Ordering::Relaxed);
+                return Err(error);
+            }
+        };
+        self.protocol.store(info.proto as u8, Ordering::Relaxed);
+        Ok(info)
+    }
+
+    /// What the server reported in reply to `HELLO`, if it was sent when
+    /// connecting
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.server_info.as_ref()
+    }
+
+    /// Sends a command and waits for its reply, error replies are returned as
+    /// a [`ClientError::ReplyError`]
+    pub async fn command(&self, command: Vec<Vec<u8>>) -> ClientResult<RespType> {
+        check_reply(self.send(RespType::command(command)).await?)
+    }
+
+    /// Sends a command that is expected to reply with `+OK`
+    pub async fn command_ok(&self, command: Vec<Vec<u8>>) -> ClientResult<()> {
+        check_ok(self.command(command).await?)
+    }
+
+    /// Queues a RESP type to be sent and waits for its reply, error replies
+    /// are returned as they are. The wait is limited by the read timeout.
+    pub async fn send(&self, resp: RespType) -> ClientResult<RespType> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.requests
+            .send(Request { resp, reply })
+            .map_err(|_| ClientError::ClosedError)?;
+
+        let reply = match self.config.read_timeout {
+            None => reply_rx.await,
+            Some(timeout) => tokio::time::timeout(timeout, reply_rx)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "reply timed out"))?,
+        };
+        reply.map_err(|_| ClientError::ClosedError)?
+    }
+}
+
+/// Writes queued requests, batching together all requests that are waiting,
+/// and hands their reply senders to the reader in the same order
+async fn write_requests<W: AsyncWrite>(
+    writer: W,
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    pending: mpsc::UnboundedSender<ReplySender>,
+) {
+    tokio::pin!(writer);
+    let mut buffer = Vec::new();
+    while let Some(request) = requests.recv().await {
+        let mut next = Some(request);
+        while let Some(request) = next {
+            if let Err(error) = request.resp.encode(&mut buffer) {
+                let _ = request.reply.send(Err(error.into()));
+            } else if let Err(failed) = pending.send(request.reply) {
+                let _ = failed.0.send(Err(ClientError::ClosedError));
+            }
+            next = requests.try_recv().ok();
+        }
+
+        if writer.write_all(&buffer).await.is_err() {
+            return;
+        }
+        buffer.clear();
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Reads replies and sends each to the oldest pending request, a single read
+/// can complete several requests at once. Each read is parsed with the
+/// protocol last negotiated with `HELLO`.
+async fn read_replies<R: AsyncRead>(
+    reader: R,
+    mut pending: mpsc::UnboundedReceiver<ReplySender>,
+    config: RespConfig,
+    protocol: Arc<AtomicU8>,
+) {
+    tokio::pin!(reader);
+    let mut parser = RespParser::new(config);
+    let mut buffer = vec![0; READ_SIZE];
+    let error = loop {
+        let read = match reader.read(&mut buffer).await {
+            Ok(0) => break ClientError::ClosedError,
+            Ok(read) => read,
+            Err(error) => break error.into(),
+        };
+        parser.config.protocol = match protocol.load(Ordering::Relaxed) {
+            2 => Protocol::Resp2,
+            _ => Protocol::Resp3,
+        };
+        let replies = match parser.read(&buffer[..read]) {
+            Ok(replies) => replies,
+            Err(error) => break error.into(),
+        };
+        for reply in replies {
+            // RESP3 pushes are not replies to any request
+            if reply.is_push() {
+                continue;
+            }
+            // Senders are queued before their request is written, so a reply
+            // without one was not asked for and is dropped
+            if let Ok(sender) = pending.try_recv() {
+                let _ = sender.send(Ok(reply));
+            }
+        }
+    };
+
+    // Fail everything still waiting, the connection can not be used anymore.
+    // The oldest request is the one the failed read was for, and gets the
+    // error itself.
+    pending.close();
+    let message = error.to_string();
+    let closed = matches!(error, ClientError::ClosedError);
+    let mut error = Some(error);
+    while let Some(sender) = pending.recv().await {
+        let error = match error.take() {
+            Some(error) => error,
+            None if closed => ClientError::ClosedError,
+            None => io::Error::new(io::ErrorKind::ConnectionAborted, message.as_str()).into(),
+        };
+        let _ = sender.send(Err(error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::{hello_reply, serve};
+    use crate::ParserError;
+    use futures_util::future::join_all;
+    use RespType::*;
+
+    #[tokio::test]
+    async fn command() -> ClientResult<()> {
+        let addr = serve(|command| match command[0].as_slice() {
+            b"PING" => SimpleString("PONG".into()),
+            _ => Error("ERR unknown command".into()),
+        });
+        let connection = AsyncConnection::connect_tcp(addr, &ConnectionConfig::default()).await?;
+        let reply = connection.command(vec!["PING".into()]).await?;
+        assert_eq!(reply, SimpleString("PONG".into()));
+        match connection.command(vec!["NOPE".into()]).await {
+            Err(ClientError::ReplyError(error)) => {
+                assert_eq!(error.to_string(), "ERR unknown command")
+            }
+            other => panic!("expected a reply error, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipelined() -> ClientResult<()> {
+        let addr = serve(|command| BulkString(command[1].clone()));
+        let connection = AsyncConnection::connect_tcp(addr, &ConnectionConfig::default()).await?;
+
+        let requests = (0..100).map(|i| {
+            let connection = connection.clone();
+            async move {
+                let reply = connection
+                    .command(vec!["ECHO".into(), i.to_string().into()])
+                    .await?;
+                assert_eq!(reply, BulkString(i.to_string().into()));
+                Ok::<_, ClientError>(())
+            }
+        });
+        for result in join_all(requests).await {
+            result?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn auth() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [auth, pass] if auth == b"AUTH" && pass == b"secret" => SimpleString("OK".into()),
+            _ => Error("NOAUTH Authentication required".into()),
+        });
+        let config = ConnectionConfig {
+            password: Some("secret".into()),
+            ..Default::default()
+        };
+        AsyncConnection::connect_tcp(addr, &config).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hello() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [hello, proto, setname, name]
+                if hello == b"HELLO" && setname == b"SETNAME" && name == b"app" =>
+            {
+                match proto.as_slice() {
+                    b"3" => Error("NOPROTO unsupported protocol version".into()),
+                    _ => hello_reply(Protocol::Resp2),
+                }
+            }
+            _ => Boolean(true),
+        });
+        let config = ConnectionConfig {
+            protocol: Some(Protocol::Resp3),
+            client_name: Some("app".into()),
+            ..Default::default()
+        };
+        let connection = AsyncConnection::connect_tcp(addr, &config).await?;
+        assert_eq!(connection.server_info().unwrap().proto, Protocol::Resp2);
+
+        // The parser now rejects RESP3 types, which fails the connection
+        assert!(matches!(
+            connection.command(vec!["PING".into()]).await,
+            Err(ClientError::ProtocolError(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hello_renegotiate() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [hello, proto] if hello == b"HELLO" && proto == b"2" => hello_reply(Protocol::Resp2),
+            [hello, proto] if hello == b"HELLO" && proto == b"3" => hello_reply(Protocol::Resp3),
+            _ => Boolean(true),
+        });
+        let config = ConnectionConfig {
+            protocol: Some(Protocol::Resp2),
+            ..Default::default()
+        };
+        let connection = AsyncConnection::connect_tcp(addr, &config).await?;
+        assert_eq!(connection.server_info().unwrap().proto, Protocol::Resp2);
+
+        // The RESP3 map replying to HELLO 3 is read on a RESP2 connection
+        let info = connection
+            .hello(Protocol::Resp3, &ConnectionConfig::default())
+            .await?;
+        assert_eq!(info.proto, Protocol::Resp3);
+        assert_eq!(
+            connection.command(vec!["PING".into()]).await?,
+            Boolean(true)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closed() -> ClientResult<()> {
+        let (client, server) = tokio::io::duplex(64);
+        let connection = AsyncConnection::new(client, &ConnectionConfig::default());
+        drop(server);
+        let result = connection.command(vec!["PING".into()]).await;
+        assert!(matches!(result, Err(ClientError::ClosedError)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn protocol_error() -> ClientResult<()> {
+        let (client, mut server) = tokio::io::duplex(64);
+        let connection = AsyncConnection::new(client, &ConnectionConfig::default());
+        tokio::spawn(async move {
+            let mut requests = [0; 28];
+            server.read_exact(&mut requests).await?;
+            server.write_all(b"x\r\n").await?;
+            // Kept open so only the malformed reply fails the requests
+            server.read(&mut requests).await
+        });
+
+        // The request the malformed reply was for gets the parser error
+        let (first, second) = tokio::join!(
+            connection.command(vec!["PING".into()]),
+            connection.command(vec!["PING".into()])
+        );
+        match first {
+            Err(ClientError::ProtocolError(error)) => {
+                assert!(matches!(error.kind(), ParserError::TypeTokenError('x')))
+            }
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+        assert!(matches!(second, Err(ClientError::IoError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn push_skipped() -> ClientResult<()> {
+        let (client, mut server) = tokio::io::duplex(64);
+        let connection = AsyncConnection::new(client, &ConnectionConfig::default());
+        let reply = tokio::spawn(async move { connection.command(vec!["PING".into()]).await });
+
+        let mut request = [0; 14];
+        server.read_exact(&mut request).await?;
+        server
+            .write_all(b">2\r\n+message\r\n+hi\r\n+PONG\r\n")
+            .await?;
+        let reply = reply.await.unwrap()?;
+        assert_eq!(reply, SimpleString("PONG".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_timeout() -> ClientResult<()> {
+        let (client, _server) = tokio::io::duplex(64);
+        let config = ConnectionConfig {
+            read_timeout: Some(std::time::Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let connection = AsyncConnection::new(client, &config);
+        match connection.command(vec!["PING".into()]).await {
+            Err(ClientError::IoError(error)) => assert_eq!(error.kind(), io::ErrorKind::TimedOut),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        Ok(())
+    }
+}
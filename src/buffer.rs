@@ -1,14 +1,13 @@
-use super::ParserError;
-use anyhow::Result;
+use super::{ParserError, Result};
 use bstr::ByteSlice;
 
 #[derive(Debug)]
-pub enum ReadlineResult {
-    Line { line: String, cursor: usize },
+pub enum ReadlineResult<'a> {
+    Line { line: &'a str, cursor: usize },
     None { cursor: usize },
 }
 
-pub fn readline(buffer: &[u8], cursor: usize, start: usize) -> Result<ReadlineResult> {
+pub fn readline(buffer: &[u8], cursor: usize, start: usize) -> Result<ReadlineResult<'_>> {
     match buffer[cursor..].find_byte(b'\r') {
         Some(cr) => {
             let end = cursor + cr;
@@ -20,10 +19,12 @@ pub fn readline(buffer: &[u8], cursor: usize, start: usize) -> Result<ReadlineRe
                 }),
                 true => match buffer[length_needed - 1] == b'\n' {
                     true => {
-                        let line = buffer[start..end].to_str()?.to_string();
-                        if line.contains('\n') {
+                        let line = std::str::from_utf8(&buffer[start..end]).map_err(|error| {
+                            ParserError::Utf8Error(error).at(start + error.valid_up_to())
+                        })?;
+                        if let Some(newline) = line.find('\n') {
                             let error = "line contains premature \\n".to_string();
-                            return Err(ParserError::ReadlineError(error).into());
+                            return Err(ParserError::ReadlineError(error).at(start + newline));
                         }
                         Ok(ReadlineResult::Line {
                             line,
@@ -31,8 +32,8 @@ pub fn readline(buffer: &[u8], cursor: usize, start: usize) -> Result<ReadlineRe
                         })
                     }
                     false => {
-                        let error = format!("expected '\\n' after '\\r', got {}", buffer[cr + 1]);
-                        Err(ParserError::ReadlineError(error).into())
+                        let error = format!("expected '\\n' after '\\r', got {}", buffer[end + 1]);
+                        Err(ParserError::ReadlineError(error).at(end + 1))
                     }
                 },
             }
@@ -56,19 +57,27 @@ pub fn readbuffer(buffer: &[u8], cursor: usize, size: usize) -> Option<(Vec<u8>,
 pub enum ReadsizeResult {
     Size { end: usize, size: usize },
     Null(usize),
+    Streamed(usize),
     None(usize),
 }
 
 pub fn readsize(buffer: &[u8], cursor: usize, start: usize) -> Result<ReadsizeResult> {
     match readline(buffer, cursor, start)? {
         ReadlineResult::Line { line, cursor: end } => {
-            let size: i64 = line.parse()?;
+            if line == "?" {
+                return Ok(ReadsizeResult::Streamed(end));
+            }
+            let size: i64 = line
+                .parse()
+                .map_err(|_| ParserError::IntegerError(line.to_string()).at(start))?;
             let result = match size {
-                invalid if size < -1 => return Err(ParserError::ReadsizeError(invalid).into()),
+                invalid if size < -1 => return Err(ParserError::ReadsizeError(invalid).at(start)),
                 -1 => ReadsizeResult::Null(end),
                 size => ReadsizeResult::Size {
                     end,
-                    size: size.try_into()?,
+                    size: size
+                        .try_into()
+                        .map_err(|_| ParserError::ReadsizeError(size).at(start))?,
                 },
             };
             Ok(result)
@@ -77,10 +86,184 @@ pub fn readsize(buffer: &[u8], cursor: usize, start: usize) -> Result<ReadsizeRe
     }
 }
 
+/// Splits an inline command into its arguments the way redis-server does.
+/// Arguments are separated by whitespace and can be quoted, double quoted
+/// arguments take escapes such as `\n` and `\x41`, single quoted ones only
+/// `\'`. Returns `None` for unbalanced quotes, or a closing quote that is not
+/// followed by whitespace.
+pub fn split_inline(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < line.len() && is_inline_space(line[i]) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = Vec::new();
+        let (mut double_quoted, mut single_quoted) = (false, false);
+        loop {
+            let done = if double_quoted {
+                match line.get(i..)? {
+                    [b'\\', b'x', high, low, ..]
+                        if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() =>
+                    {
+                        arg.push(hex(*high) << 4 | hex(*low));
+                        i += 3;
+                        false
+                    }
+                    [b'\\', escaped, ..] => {
+                        arg.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => *other,
+                        });
+                        i += 1;
+                        false
+                    }
+                    [b'"', rest @ ..] => closing_quote(rest)?,
+                    [byte, ..] => {
+                        arg.push(*byte);
+                        false
+                    }
+                    [] => return None,
+                }
+            } else if single_quoted {
+                match line.get(i..)? {
+                    [b'\\', b'\'', ..] => {
+                        arg.push(b'\'');
+                        i += 1;
+                        false
+                    }
+                    [b'\'', rest @ ..] => closing_quote(rest)?,
+                    [byte, ..] => {
+                        arg.push(*byte);
+                        false
+                    }
+                    [] => return None,
+                }
+            } else {
+                match line.get(i) {
+                    None => true,
+                    Some(byte) if is_inline_space(*byte) => true,
+                    Some(b'"') => {
+                        double_quoted = true;
+                        false
+                    }
+                    Some(b'\'') => {
+                        single_quoted = true;
+                        false
+                    }
+                    Some(byte) => {
+                        arg.push(*byte);
+                        false
+                    }
+                }
+            };
+            if i < line.len() {
+                i += 1;
+            }
+            if done {
+                break;
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// A closing quote ends the argument, and must be followed by whitespace or
+/// the end of the line
+fn closing_quote(rest: &[u8]) -> Option<bool> {
+    match rest.first() {
+        Some(byte) if !is_inline_space(*byte) => None,
+        _ => Some(true),
+    }
+}
+
+/// Separates inline arguments, the bytes C `isspace` matches and `\0` as in
+/// redis-server
+fn is_inline_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r' | b'\0')
+}
+
+fn hex(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod split_inline {
+        use super::*;
+
+        fn split(line: &str) -> Option<Vec<String>> {
+            let args = split_inline(line.as_bytes())?;
+            Some(
+                args.into_iter()
+                    .map(|arg| String::from_utf8(arg).unwrap())
+                    .collect(),
+            )
+        }
+
+        #[test]
+        fn whitespace() {
+            assert_eq!(split("PING"), Some(vec!["PING".into()]));
+            assert_eq!(
+                split("  SET\ta   b  "),
+                Some(vec!["SET".into(), "a".into(), "b".into()])
+            );
+            assert_eq!(split(""), Some(vec![]));
+            assert_eq!(split("   "), Some(vec![]));
+            // Vertical tabs, form feeds and NUL bytes separate too
+            assert_eq!(
+                split("\x0bGET\x0ca\0"),
+                Some(vec!["GET".into(), "a".into()])
+            );
+            assert_eq!(split("'a'\x0b\"b\"\0"), Some(vec!["a".into(), "b".into()]));
+        }
+
+        #[test]
+        fn quotes() {
+            assert_eq!(
+                split(r#"SET a "b c""#),
+                Some(vec!["SET".into(), "a".into(), "b c".into()])
+            );
+            assert_eq!(
+                split("SET 'a b' ''"),
+                Some(vec!["SET".into(), "a b".into(), "".into()])
+            );
+            // Quotes can also start part way through an argument
+            assert_eq!(split(r#"a"b c""#), Some(vec!["ab c".into()]));
+        }
+
+        #[test]
+        fn escapes() {
+            assert_eq!(
+                split(r#""a\nb\x41\"\\" 'it\'s' 'no\n'"#),
+                Some(vec!["a\nbA\"\\".into(), "it's".into(), "no\\n".into()])
+            );
+            assert_eq!(split(r#""\xZZ""#), Some(vec!["xZZ".into()]));
+        }
+
+        #[test]
+        fn unbalanced() {
+            assert_eq!(split(r#"SET a "b"#), None);
+            assert_eq!(split("SET a 'b"), None);
+            assert_eq!(split(r#"SET "a"b"#), None);
+            assert_eq!(split(r#"SET "a\"#), None);
+        }
+    }
+
     mod readline {
         use super::*;
 
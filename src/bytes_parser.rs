@@ -0,0 +1,359 @@
+use bytes::{Bytes, BytesMut};
+
+use super::error::{ParserError, Result};
+use super::resp_ref::decode;
+use super::RespConfig;
+use super::RespRef;
+use super::RespType;
+
+/// Enum for RESP types returned by the [`BytesParser`], bulk data is kept as
+/// [`Bytes`] slices that share the parser's receive buffer. See [`RespType`]
+/// for details on each variant.
+#[derive(PartialEq, Debug, Clone)]
+pub enum BytesFrame {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Bytes),
+    Array(Vec<BytesFrame>),
+    Null,
+    NullArray,
+    Map(Vec<(BytesFrame, BytesFrame)>),
+    Set(Vec<BytesFrame>),
+    Push(Vec<BytesFrame>),
+    Attribute {
+        attributes: Vec<(BytesFrame, BytesFrame)>,
+        reply: Box<BytesFrame>,
+    },
+    VerbatimString {
+        format: String,
+        text: Bytes,
+    },
+    BlobError(Bytes),
+    Nil,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(String),
+}
+
+impl From<RespType> for BytesFrame {
+    fn from(resp: RespType) -> Self {
+        fn pairs(pairs: Vec<(RespType, RespType)>) -> Vec<(BytesFrame, BytesFrame)> {
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect()
+        }
+        fn elements(elements: Vec<RespType>) -> Vec<BytesFrame> {
+            elements.into_iter().map(BytesFrame::from).collect()
+        }
+
+        match resp {
+            RespType::SimpleString(string) => BytesFrame::SimpleString(string),
+            RespType::Error(string) => BytesFrame::Error(string),
+            RespType::Integer(integer) => BytesFrame::Integer(integer),
+            RespType::BulkString(string) => BytesFrame::BulkString(string.into()),
+            RespType::Array(array) => BytesFrame::Array(elements(array)),
+            RespType::Null => BytesFrame::Null,
+            RespType::NullArray => BytesFrame::NullArray,
+            RespType::Map(map) => BytesFrame::Map(pairs(map)),
+            RespType::Set(set) => BytesFrame::Set(elements(set)),
+            RespType::Push(push) => BytesFrame::Push(elements(push)),
+            RespType::Attribute { attributes, reply } => BytesFrame::Attribute {
+                attributes: pairs(attributes),
+                reply: Box::new((*reply).into()),
+            },
+            RespType::VerbatimString { format, text } => BytesFrame::VerbatimString {
+                format,
+                text: text.into(),
+            },
+            RespType::BlobError(string) => BytesFrame::BlobError(string.into()),
+            RespType::Nil => BytesFrame::Nil,
+            RespType::Boolean(boolean) => BytesFrame::Boolean(boolean),
+            RespType::Double(double) => BytesFrame::Double(double),
+            RespType::BigNumber(string) => BytesFrame::BigNumber(string),
+        }
+    }
+}
+
+impl From<BytesFrame> for RespType {
+    fn from(frame: BytesFrame) -> Self {
+        fn pairs(pairs: Vec<(BytesFrame, BytesFrame)>) -> Vec<(RespType, RespType)> {
+            pairs
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect()
+        }
+        fn elements(elements: Vec<BytesFrame>) -> Vec<RespType> {
+            elements.into_iter().map(RespType::from).collect()
+        }
+
+        match frame {
+            BytesFrame::SimpleString(string) => RespType::SimpleString(string),
+            BytesFrame::Error(string) => RespType::Error(string),
+            BytesFrame::Integer(integer) => RespType::Integer(integer),
+            BytesFrame::BulkString(string) => RespType::BulkString(string.into()),
+            BytesFrame::Array(array) => RespType::Array(elements(array)),
+            BytesFrame::Null => RespType::Null,
+            BytesFrame::NullArray => RespType::NullArray,
+            BytesFrame::Map(map) => RespType::Map(pairs(map)),
+            BytesFrame::Set(set) => RespType::Set(elements(set)),
+            BytesFrame::Push(push) => RespType::Push(elements(push)),
+            BytesFrame::Attribute { attributes, reply } => RespType::Attribute {
+                attributes: pairs(attributes),
+                reply: Box::new((*reply).into()),
+            },
+            BytesFrame::VerbatimString { format, text } => RespType::VerbatimString {
+                format,
+                text: text.into(),
+            },
+            BytesFrame::BlobError(string) => RespType::BlobError(string.into()),
+            BytesFrame::Nil => RespType::Nil,
+            BytesFrame::Boolean(boolean) => RespType::Boolean(boolean),
+            BytesFrame::Double(double) => RespType::Double(double),
+            BytesFrame::BigNumber(string) => RespType::BigNumber(string),
+        }
+    }
+}
+
+/// A parser that keeps its storage in a [`BytesMut`], bulk data is returned
+/// as [`Bytes`] slices of the receive buffer instead of being copied.
+///
+/// Unlike the [`RespParser`](crate::RespParser) no intermediate state is kept,
+/// frames are decoded with the same decoder as [`parse_ref`](crate::parse_ref)
+/// and incomplete data is re-parsed from the start of the pending frame on the
+/// next read. This is cheap for large bulk strings, where only the header is read
+/// again, but can be slower for large aggregates trickling in.
+pub struct BytesParser {
+    buffer: BytesMut,
+    consumed: usize,
+    /// Configuration structure for memory limits
+    pub config: RespConfig,
+}
+
+impl Default for BytesParser {
+    fn default() -> Self {
+        Self::new(RespConfig::default())
+    }
+}
+
+impl BytesParser {
+    /// Creates a new instance, can use [`BytesParser::default`] for common setups
+    pub fn new(config: RespConfig) -> Self {
+        BytesParser {
+            buffer: BytesMut::new(),
+            consumed: 0,
+            config,
+        }
+    }
+
+    /// Copy and parses the provided buffer, returns a list of [`BytesFrame`] results
+    pub fn read(&mut self, buffer: &[u8]) -> Result<Vec<BytesFrame>> {
+        self.buffer.extend_from_slice(buffer);
+
+        if self.buffer.len() > self.config.max_buffer_size {
+            let error = ParserError::SizeExceededError.at(self.config.max_buffer_size);
+            let error = error.shifted(self.consumed);
+            self.consumed += self.buffer.len();
+            self.buffer.clear();
+            return Err(error);
+        }
+
+        // On error the split off data is dropped, leaving the buffer empty
+        let mut data = self.buffer.split().freeze();
+        let mut items = Vec::new();
+        let mut cursor = 0;
+        loop {
+            match decode(&data, cursor, &self.config, 0) {
+                Ok(Some((resp, end))) => {
+                    items.push(from_ref(&data, resp));
+                    cursor = end;
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    let error = error.shifted(self.consumed);
+                    self.consumed += data.len();
+                    return Err(error);
+                }
+            }
+        }
+        self.consumed += cursor;
+
+        // The remainder can only be reused in place if no frames share it
+        let remainder = data.split_off(cursor);
+        drop(data);
+        self.buffer = match remainder.try_into_mut() {
+            Ok(remainder) => remainder,
+            Err(remainder) => BytesMut::from(&remainder[..]),
+        };
+        Ok(items)
+    }
+}
+
+/// Converts a view of a frozen buffer into a frame, slicing the bulk data out of
+/// the buffer rather than copying it
+fn from_ref(data: &Bytes, resp: RespRef) -> BytesFrame {
+    let pairs = |pairs: Vec<(RespRef, RespRef)>| {
+        pairs
+            .into_iter()
+            .map(|(key, value)| (from_ref(data, key), from_ref(data, value)))
+            .collect()
+    };
+    let elements = |elements: Vec<RespRef>| {
+        elements
+            .into_iter()
+            .map(|element| from_ref(data, element))
+            .collect()
+    };
+
+    match resp {
+        RespRef::SimpleString(string) => BytesFrame::SimpleString(string.to_string()),
+        RespRef::Error(string) => BytesFrame::Error(string.to_string()),
+        RespRef::Integer(integer) => BytesFrame::Integer(integer),
+        RespRef::BulkString(string) => BytesFrame::BulkString(data.slice_ref(string)),
+        RespRef::Array(array) => BytesFrame::Array(elements(array)),
+        RespRef::Null => BytesFrame::Null,
+        RespRef::NullArray => BytesFrame::NullArray,
+        RespRef::Map(map) => BytesFrame::Map(pairs(map)),
+        RespRef::Set(set) => BytesFrame::Set(elements(set)),
+        RespRef::Push(push) => BytesFrame::Push(elements(push)),
+        RespRef::Attribute { attributes, reply } => BytesFrame::Attribute {
+            attributes: pairs(attributes),
+            reply: Box::new(from_ref(data, *reply)),
+        },
+        RespRef::VerbatimString { format, text } => BytesFrame::VerbatimString {
+            format: format.to_string(),
+            text: data.slice_ref(text),
+        },
+        RespRef::BlobError(string) => BytesFrame::BlobError(data.slice_ref(string)),
+        RespRef::Nil => BytesFrame::Nil,
+        RespRef::Boolean(boolean) => BytesFrame::Boolean(boolean),
+        RespRef::Double(double) => BytesFrame::Double(double),
+        RespRef::BigNumber(string) => BytesFrame::BigNumber(string.to_string()),
+        RespRef::StreamedString(chunks) => BytesFrame::BulkString(chunks.concat().into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BytesFrame::*;
+
+    fn test_parser_ok(buffer: &[u8]) -> Vec<BytesFrame> {
+        let mut parser = BytesParser::default();
+        match parser.read(buffer) {
+            Ok(results) => results,
+            other => panic!("result was not Ok(), was {:#?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_start() {
+        assert_eq!(test_parser_ok(b""), Vec::new());
+    }
+
+    #[test]
+    fn shares_buffer() {
+        let results = test_parser_ok(b"$5\r\nhello\r\n$5\r\nworld\r\n");
+
+        assert_eq!(results.len(), 2);
+        match (&results[0], &results[1]) {
+            (BulkString(hello), BulkString(world)) => {
+                assert_eq!(hello, "hello");
+                assert_eq!(world, "world");
+                let distance = world.as_ptr() as usize - hello.as_ptr() as usize;
+                assert_eq!(distance, 11);
+            }
+            other => panic!("Expected two BulkString variants, got {:#?}", other),
+        }
+    }
+
+    #[test]
+    fn complex_nested() {
+        let input = b"*3\r\n*-1\r\n*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n*5\r\n+test\r\n-test3\r\n:-12345\r\n$2\r\nab\r\n$-1\r\n";
+        let results = test_parser_ok(input);
+
+        assert_eq!(results.len(), 1);
+        let resp: RespType = results[0].clone().into();
+        assert_eq!(resp.as_bytes(), input);
+    }
+
+    #[test]
+    fn complex_nested_onebyte() -> Result<()> {
+        let mut parser = BytesParser::default();
+        let input = b"*2\r\n%1\r\n=7\r\ntxt:abc\r\n~1\r\n$?\r\n;2\r\nhi\r\n;0\r\n|1\r\n+ttl\r\n:1\r\n!3\r\nERR\r\n";
+        let (last, rest) = input.split_last().unwrap();
+        for byte in rest {
+            assert_eq!(parser.read(&[*byte])?, Vec::new());
+        }
+
+        let results = parser.read(&[*last])?;
+        assert_eq!(
+            results,
+            vec![Array(vec![
+                Map(vec![(
+                    VerbatimString {
+                        format: "txt".into(),
+                        text: Bytes::from_static(b"abc")
+                    },
+                    Set(vec![BulkString(Bytes::from_static(b"hi"))])
+                )]),
+                Attribute {
+                    attributes: vec![(SimpleString("ttl".into()), Integer(1))],
+                    reply: Box::new(BlobError(Bytes::from_static(b"ERR"))),
+                }
+            ])]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn split_read() -> Result<()> {
+        let mut parser = BytesParser::default();
+        assert_eq!(
+            parser.read(b"+OK\r\n$11\r\nhello")?,
+            vec![SimpleString("OK".into())]
+        );
+        assert_eq!(parser.read(b" worl")?, Vec::new());
+        assert_eq!(
+            parser.read(b"d\r\n:1\r\n")?,
+            vec![BulkString(Bytes::from_static(b"hello world")), Integer(1)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_aggregate() {
+        let results = test_parser_ok(b"%?\r\n+a\r\n#t\r\n.\r\n");
+
+        assert_eq!(
+            results,
+            vec![Map(vec![(SimpleString("a".into()), Boolean(true))])]
+        );
+    }
+
+    #[test]
+    fn discard_attributes() -> Result<()> {
+        let mut parser = BytesParser::new(RespConfig {
+            discard_attributes: true,
+            ..Default::default()
+        });
+        let results = parser.read(b"|1\r\n+ttl\r\n:1\r\n,1.5\r\n")?;
+        assert_eq!(results, vec![Double(1.5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn size_exceeded() {
+        let mut parser = BytesParser::new(RespConfig::new(4, 1024));
+        assert!(parser.read(b"$5\r\nhello\r\n").is_err());
+    }
+
+    #[test]
+    fn error_recovery() -> Result<()> {
+        let mut parser = BytesParser::default();
+        assert!(parser.read(b"forgot my type!").is_err());
+        assert_eq!(parser.read(b"_\r\n")?, vec![Nil]);
+        Ok(())
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+
+use super::cluster::command_keys;
+use super::connection::{check_ok, check_reply};
+use super::error::{ClientError, ClientResult};
+use super::RespType;
+use super::{Connection, ConnectionConfig, Protocol};
+
+/// Channel invalidation messages are published to on RESP2 connections
+const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// Which keys the server sends invalidations for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrackingMode {
+    /// Keys this client has read, the server remembers them per client
+    Default,
+    /// Every key starting with one of the prefixes, or every key when no
+    /// prefixes are given. The server keeps no per client state.
+    Broadcast(Vec<Vec<u8>>),
+}
+
+/// A connection with a local cache of read replies, kept up to date with
+/// `CLIENT TRACKING`.
+///
+/// Replies to single key commands sent with [`CachingConnection::cached`] are
+/// stored per key, and evicted when the server reports the key changed. On
+/// RESP3 the invalidations arrive as pushes on the same connection, on RESP2
+/// they are published to `__redis__:invalidate` on a second connection that
+/// tracking is redirected to.
+pub struct CachingConnection {
+    connection: Connection,
+    invalidations: Option<Connection>,
+    cache: HashMap<Vec<u8>, HashMap<Vec<Vec<u8>>, RespType>>,
+}
+
+impl CachingConnection {
+    /// Switches the connection to RESP3 with `HELLO 3` and turns tracking on.
+    /// Fails with [`ClientError::Resp3UnsupportedError`] if the server only
+    /// speaks RESP2, use [`CachingConnection::with_redirect`] for those.
+    pub fn new(mut connection: Connection, mode: TrackingMode) -> ClientResult<Self> {
+        let info = connection.hello(Protocol::Resp3, &ConnectionConfig::default())?;
+        // Without RESP3 the server would drop the invalidations, leaving
+        // stale replies in the cache
+        if info.proto != Protocol::Resp3 {
+            return Err(ClientError::Resp3UnsupportedError);
+        }
+        let mut caching = CachingConnection {
+            connection,
+            invalidations: None,
+            cache: HashMap::new(),
+        };
+        caching.track(None, mode)?;
+        Ok(caching)
+    }
+
+    /// Turns tracking on for a RESP2 connection, redirecting invalidations to
+    /// the second connection which is subscribed to `__redis__:invalidate`
+    pub fn with_redirect(
+        connection: Connection,
+        mut invalidations: Connection,
+        mode: TrackingMode,
+    ) -> ClientResult<Self> {
+        let id = match invalidations.command(vec!["CLIENT".into(), "ID".into()])? {
+            RespType::Integer(id) => id,
+            other => return Err(ClientError::UnexpectedReplyError(other)),
+        };
+        invalidations.send(&RespType::command(vec![
+            "SUBSCRIBE".into(),
+            INVALIDATE_CHANNEL.into(),
+        ]))?;
+        match check_reply(invalidations.receive()?)? {
+            RespType::Array(reply) if reply.first().and_then(bytes) == Some(b"subscribe") => {}
+            other => return Err(ClientError::UnexpectedReplyError(other)),
+        }
+
+        let mut caching = CachingConnection {
+            connection,
+            invalidations: Some(invalidations),
+            cache: HashMap::new(),
+        };
+        caching.track(Some(id), mode)?;
+        Ok(caching)
+    }
+
+    fn track(&mut self, redirect: Option<i64>, mode: TrackingMode) -> ClientResult<()> {
+        let mut command = vec!["CLIENT".into(), "TRACKING".into(), "ON".into()];
+        if let Some(id) = redirect {
+            command.extend(["REDIRECT".into(), id.to_string().into()]);
+        }
+        if let TrackingMode::Broadcast(prefixes) = mode {
+            command.push("BCAST".into());
+            for prefix in prefixes {
+                command.extend(["PREFIX".into(), prefix]);
+            }
+        }
+        check_ok(self.command(command)?)
+    }
+
+    /// Sends a single key command such as `GET`, replying from the cache when
+    /// the key has not changed since the last time. Error replies and
+    /// commands that do not have exactly one key are not cached.
+    pub fn cached(&mut self, command: Vec<Vec<u8>>) -> ClientResult<RespType> {
+        self.process_invalidations()?;
+        let resp = RespType::command(command.clone());
+        let key = match command_keys(&resp).as_slice() {
+            [key] => key.to_vec(),
+            _ => return self.command(command),
+        };
+        if let Some(reply) = self
+            .cache
+            .get(&key)
+            .and_then(|replies| replies.get(&command))
+        {
+            return Ok(reply.clone());
+        }
+
+        let reply = self.command(command.clone())?;
+        self.cache
+            .entry(key)
+            .or_default()
+            .insert(command, reply.clone());
+        Ok(reply)
+    }
+
+    /// Sends a command without caching its reply, error replies are returned
+    /// as a [`ClientError::ReplyError`]. Pushes other than invalidations are
+    /// skipped, as with [`Connection::command`].
+    pub fn command(&mut self, command: Vec<Vec<u8>>) -> ClientResult<RespType> {
+        self.connection.send(&RespType::command(command))?;
+        loop {
+            let reply = self.connection.receive()?;
+            if self.invalidate(&reply) || reply.is_push() {
+                continue;
+            }
+            return check_reply(reply);
+        }
+    }
+
+    /// Number of keys with cached replies
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Checks if nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Drops every cached reply
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Drops the cache, returning the connection. Tracking is left on.
+    pub fn into_connection(self) -> Connection {
+        self.connection
+    }
+
+    /// Applies the invalidations that arrived since the last command. Other
+    /// pushes are skipped, any other frame means replies are out of step and
+    /// is returned as a [`ClientError::UnexpectedReplyError`].
+    fn process_invalidations(&mut self) -> ClientResult<()> {
+        loop {
+            let frame = match &mut self.invalidations {
+                Some(invalidations) => invalidations.try_receive()?,
+                None => self.connection.try_receive()?,
+            };
+            match frame {
+                Some(frame) if self.invalidate(&frame) || frame.is_push() => {}
+                Some(frame) => return Err(ClientError::UnexpectedReplyError(frame)),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Evicts the keys of an invalidation frame, returning `false` if the
+    /// frame is not one
+    fn invalidate(&mut self, frame: &RespType) -> bool {
+        match invalidated_keys(frame) {
+            Some(Some(keys)) => {
+                for key in keys {
+                    self.cache.remove(key);
+                }
+                true
+            }
+            // Sent when the server flushed its data or lost track of keys
+            Some(None) => {
+                self.cache.clear();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Decodes an invalidation, from a RESP3 `invalidate` push or a RESP2 message
+/// on `__redis__:invalidate`. The keys are `None` when everything should be
+/// evicted.
+fn invalidated_keys(frame: &RespType) -> Option<Option<Vec<&[u8]>>> {
+    let keys = match frame {
+        RespType::Push(elements) => match elements.as_slice() {
+            [name, keys] if bytes(name)? == b"invalidate" => keys,
+            _ => return None,
+        },
+        RespType::Array(elements) => match elements.as_slice() {
+            [name, channel, keys]
+                if bytes(name)? == b"message"
+                    && bytes(channel)? == INVALIDATE_CHANNEL.as_bytes() =>
+            {
+                keys
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match keys {
+        RespType::Array(keys) => Some(Some(keys.iter().filter_map(bytes).collect())),
+        _ => Some(None),
+    }
+}
+
+fn bytes(resp: &RespType) -> Option<&[u8]> {
+    match resp {
+        RespType::BulkString(bytes) => Some(bytes),
+        RespType::SimpleString(string) => Some(string.as_bytes()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::{hello_reply, serve, serve_raw};
+    use crate::RespParser;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use RespType::*;
+
+    fn bulk(data: &str) -> RespType {
+        BulkString(data.into())
+    }
+
+    fn get(key: &str) -> Vec<Vec<u8>> {
+        vec!["GET".into(), key.into()]
+    }
+
+    fn connect(addr: SocketAddr) -> ClientResult<Connection> {
+        Connection::connect_tcp(addr, &ConnectionConfig::default())
+    }
+
+    /// A RESP3 server answering `GET` with the key and how many times it was
+    /// read, `SET` is followed by an invalidation push for the key
+    fn resp3_server() -> SocketAddr {
+        let mut reads = 0;
+        serve_raw(move |command| match command[0].as_slice() {
+            b"HELLO" if command[1] == b"2" => hello_reply(Protocol::Resp2).as_bytes(),
+            b"HELLO" => hello_reply(Protocol::Resp3).as_bytes(),
+            b"CLIENT" => SimpleString("OK".into()).as_bytes(),
+            b"GET" => {
+                reads += 1;
+                let value = format!("{}{}", String::from_utf8_lossy(&command[1]), reads);
+                bulk(&value).as_bytes()
+            }
+            b"SET" => {
+                let mut reply = SimpleString("OK".into()).as_bytes();
+                reply.extend(
+                    Push(vec![
+                        bulk("invalidate"),
+                        Array(vec![BulkString(command[1].clone())]),
+                    ])
+                    .as_bytes(),
+                );
+                reply
+            }
+            b"FLUSHALL" => {
+                let mut reply = Push(vec![bulk("invalidate"), Nil]).as_bytes();
+                reply.extend(SimpleString("OK".into()).as_bytes());
+                reply
+            }
+            _ => Error("ERR unknown command".into()).as_bytes(),
+        })
+    }
+
+    #[test]
+    fn decode() {
+        let push = Push(vec![bulk("invalidate"), Array(vec![bulk("a"), bulk("b")])]);
+        assert_eq!(invalidated_keys(&push), Some(Some(vec![&b"a"[..], b"b"])));
+        assert_eq!(
+            invalidated_keys(&Push(vec![bulk("invalidate"), Nil])),
+            Some(None)
+        );
+
+        let message = Array(vec![
+            bulk("message"),
+            bulk("__redis__:invalidate"),
+            Array(vec![bulk("a")]),
+        ]);
+        assert_eq!(invalidated_keys(&message), Some(Some(vec![&b"a"[..]])));
+        let message = Array(vec![bulk("message"), bulk("news"), bulk("hi")]);
+        assert_eq!(invalidated_keys(&message), None);
+        assert_eq!(
+            invalidated_keys(&Push(vec![bulk("message"), bulk("hi")])),
+            None
+        );
+        assert_eq!(invalidated_keys(&bulk("a")), None);
+    }
+
+    #[test]
+    fn resp3() -> ClientResult<()> {
+        let connection = connect(resp3_server())?;
+        let mut caching = CachingConnection::new(connection, TrackingMode::Default)?;
+
+        assert_eq!(caching.cached(get("a"))?, bulk("a1"));
+        assert_eq!(caching.cached(get("a"))?, bulk("a1"));
+        assert_eq!(caching.cached(get("b"))?, bulk("b2"));
+        assert_eq!(caching.len(), 2);
+
+        // The push for a arrives after the reply to SET, and is picked up
+        // before the next cached read
+        caching.command(vec!["SET".into(), "a".into(), "1".into()])?;
+        assert_eq!(caching.cached(get("a"))?, bulk("a3"));
+        assert_eq!(caching.cached(get("b"))?, bulk("b2"));
+
+        // The push arrives ahead of the reply while waiting for it
+        caching.command(vec!["FLUSHALL".into()])?;
+        assert!(caching.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn other_pushes() -> ClientResult<()> {
+        // Every reply comes after a pub/sub message
+        let addr = serve_raw(|command| {
+            let mut reply = Push(vec![bulk("message"), bulk("news"), bulk("hi")]).as_bytes();
+            reply.extend(match command[0].as_slice() {
+                b"HELLO" => hello_reply(Protocol::Resp3).as_bytes(),
+                b"CLIENT" => SimpleString("OK".into()).as_bytes(),
+                b"PING" => SimpleString("PONG".into()).as_bytes(),
+                _ => BulkString(command[1].clone()).as_bytes(),
+            });
+            reply
+        });
+        let mut caching = CachingConnection::new(connect(addr)?, TrackingMode::Default)?;
+        for _ in 0..2 {
+            assert_eq!(
+                caching.command(vec!["PING".into()])?,
+                SimpleString("PONG".into())
+            );
+        }
+        assert_eq!(caching.cached(get("a"))?, bulk("a"));
+        assert_eq!(caching.cached(get("a"))?, bulk("a"));
+        assert_eq!(caching.cached(get("b"))?, bulk("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn resp2_only() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [hello, proto] if hello == b"HELLO" && proto == b"2" => hello_reply(Protocol::Resp2),
+            _ => Error("NOPROTO unsupported protocol version".into()),
+        });
+        assert!(matches!(
+            CachingConnection::new(connect(addr)?, TrackingMode::Default),
+            Err(ClientError::Resp3UnsupportedError)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn resp2_configured() -> ClientResult<()> {
+        let config = ConnectionConfig {
+            protocol: Some(Protocol::Resp2),
+            ..Default::default()
+        };
+        let connection = Connection::connect_tcp(resp3_server(), &config)?;
+        let mut caching = CachingConnection::new(connection, TrackingMode::Default)?;
+        assert_eq!(caching.cached(get("a"))?, bulk("a1"));
+        Ok(())
+    }
+
+    #[test]
+    fn uncached() -> ClientResult<()> {
+        let connection = connect(resp3_server())?;
+        let mut caching = CachingConnection::new(connection, TrackingMode::Default)?;
+        assert!(matches!(
+            caching.cached(vec!["NOPE".into(), "a".into()]),
+            Err(ClientError::ReplyError(_))
+        ));
+        assert!(caching.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn broadcast() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [_hello, _] => hello_reply(Protocol::Resp3),
+            [_client, _tracking, _on, bcast, prefix, user, prefix2, session]
+                if bcast == b"BCAST"
+                    && prefix == b"PREFIX"
+                    && user == b"user:"
+                    && prefix2 == b"PREFIX"
+                    && session == b"session:" =>
+            {
+                SimpleString("OK".into())
+            }
+            _ => Error("ERR unexpected".into()),
+        });
+        let mode = TrackingMode::Broadcast(vec!["user:".into(), "session:".into()]);
+        CachingConnection::new(connect(addr)?, mode)?;
+        Ok(())
+    }
+
+    #[test]
+    fn resp2_redirect() -> ClientResult<()> {
+        let main = serve(move |command| match command.as_slice() {
+            [_client, _tracking, _on, redirect, id] if redirect == b"REDIRECT" && id == b"7" => {
+                SimpleString("OK".into())
+            }
+            [get, key] if get == b"GET" => BulkString(key.clone()),
+            _ => Error("ERR unexpected".into()),
+        });
+
+        // The invalidation connection publishes a message for each key sent
+        // on the channel, acknowledging once it was written
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let invalidations = listener.local_addr()?;
+        let (keys, keys_rx) = mpsc::channel::<&str>();
+        let (written, written_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut parser = RespParser::default();
+            let mut buffer = [0; 1024];
+            let mut commands = 0;
+            while commands < 2 {
+                let read = stream.read(&mut buffer).unwrap();
+                for command in parser.read(&buffer[..read]).unwrap() {
+                    let reply = match commands {
+                        0 => Integer(7),
+                        _ => Array(vec![
+                            bulk("subscribe"),
+                            bulk(INVALIDATE_CHANNEL),
+                            Integer(1),
+                        ]),
+                    };
+                    assert!(matches!(command, Array(_)));
+                    stream.write_all(&reply.as_bytes()).unwrap();
+                    commands += 1;
+                }
+            }
+            for key in keys_rx {
+                let message = Array(vec![
+                    bulk("message"),
+                    bulk(INVALIDATE_CHANNEL),
+                    Array(vec![bulk(key)]),
+                ]);
+                stream.write_all(&message.as_bytes()).unwrap();
+                written.send(()).unwrap();
+            }
+        });
+
+        let mut caching = CachingConnection::with_redirect(
+            connect(main)?,
+            connect(invalidations)?,
+            TrackingMode::Default,
+        )?;
+        assert_eq!(caching.cached(get("a"))?, bulk("a"));
+        assert_eq!(caching.len(), 1);
+
+        keys.send("a").unwrap();
+        written_rx.recv().unwrap();
+        // The message can take a moment to become readable after the write
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !caching.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+            caching.process_invalidations()?;
+        }
+        assert!(caching.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use super::error::{ClientError, ClientResult};
+use super::RespType;
+
+/// Number of hash slots in a Redis Cluster
+pub const SLOTS: u16 = 16384;
+
+/// CRC16 lookup table, XMODEM variant with polynomial 0x1021
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| {
+        (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+    })
+}
+
+/// Hash slot of a key. When the key contains a non-empty `{hashtag}`, only
+/// the hashtag is hashed, so related keys can be kept in one slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let hashed = match key.iter().position(|byte| *byte == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|byte| *byte == b'}') {
+            Some(0) | None => key,
+            Some(length) => &key[open + 1..open + 1 + length],
+        },
+        None => key,
+    };
+    crc16(hashed) % SLOTS
+}
+
+/// Where the keys of a command are found among its arguments
+enum KeySpec {
+    /// No keys, or a command that is not known
+    None,
+    /// Every argument from `first`, leaving out `skip_last` at the end, taking
+    /// every `step`th
+    Range {
+        first: usize,
+        skip_last: usize,
+        step: usize,
+    },
+    /// Fixed argument positions
+    Positions(&'static [usize]),
+    /// A count of keys at `index`, followed by the keys
+    NumKeys { index: usize },
+    /// A key, then a count of keys followed by the keys
+    DestinationNumKeys,
+    /// The keys follow a `STREAMS` argument, and are followed by as many IDs
+    Streams,
+}
+
+fn key_spec(name: &[u8]) -> KeySpec {
+    let name = name.to_ascii_uppercase();
+    match name.as_slice() {
+        b"GET" | b"SET" | b"SETNX" | b"SETEX" | b"PSETEX" | b"GETSET" | b"GETDEL" | b"GETEX"
+        | b"APPEND" | b"STRLEN" | b"INCR" | b"INCRBY" | b"INCRBYFLOAT" | b"DECR" | b"DECRBY"
+        | b"GETRANGE" | b"SETRANGE" | b"GETBIT" | b"SETBIT" | b"BITCOUNT" | b"BITPOS"
+        | b"BITFIELD" | b"EXPIRE" | b"PEXPIRE" | b"EXPIREAT" | b"PEXPIREAT" | b"EXPIRETIME"
+        | b"TTL" | b"PTTL" | b"PERSIST" | b"TYPE" | b"DUMP" | b"RESTORE" | b"HGET" | b"HSET"
+        | b"HSETNX" | b"HMSET" | b"HMGET" | b"HDEL" | b"HEXISTS" | b"HGETALL" | b"HKEYS"
+        | b"HVALS" | b"HLEN" | b"HINCRBY" | b"HINCRBYFLOAT" | b"HSTRLEN" | b"HSCAN"
+        | b"HRANDFIELD" | b"LPUSH" | b"RPUSH" | b"LPUSHX" | b"RPUSHX" | b"LPOP" | b"RPOP"
+        | b"LLEN" | b"LRANGE" | b"LINDEX" | b"LSET" | b"LREM" | b"LTRIM" | b"LINSERT" | b"LPOS"
+        | b"SADD" | b"SREM" | b"SMEMBERS" | b"SISMEMBER" | b"SMISMEMBER" | b"SCARD" | b"SPOP"
+        | b"SRANDMEMBER" | b"SSCAN" | b"ZADD" | b"ZREM" | b"ZSCORE" | b"ZMSCORE" | b"ZINCRBY"
+        | b"ZCARD" | b"ZCOUNT" | b"ZLEXCOUNT" | b"ZRANGE" | b"ZRANGEBYSCORE" | b"ZRANGEBYLEX"
+        | b"ZREVRANGE" | b"ZREVRANGEBYSCORE" | b"ZREVRANGEBYLEX" | b"ZRANK" | b"ZREVRANK"
+        | b"ZREMRANGEBYRANK" | b"ZREMRANGEBYSCORE" | b"ZREMRANGEBYLEX" | b"ZSCAN" | b"ZPOPMIN"
+        | b"ZPOPMAX" | b"ZRANDMEMBER" | b"XADD" | b"XLEN" | b"XRANGE" | b"XREVRANGE" | b"XDEL"
+        | b"XTRIM" | b"XACK" | b"XCLAIM" | b"XAUTOCLAIM" | b"XPENDING" | b"PFADD" | b"GEOADD"
+        | b"GEODIST" | b"GEOPOS" | b"GEOHASH" | b"GEOSEARCH" => KeySpec::Positions(&[1]),
+        b"DEL" | b"UNLINK" | b"EXISTS" | b"TOUCH" | b"MGET" | b"WATCH" | b"SINTER" | b"SUNION"
+        | b"SDIFF" | b"SINTERSTORE" | b"SUNIONSTORE" | b"SDIFFSTORE" | b"PFCOUNT" | b"PFMERGE" => {
+            KeySpec::Range {
+                first: 1,
+                skip_last: 0,
+                step: 1,
+            }
+        }
+        b"MSET" | b"MSETNX" => KeySpec::Range {
+            first: 1,
+            skip_last: 0,
+            step: 2,
+        },
+        b"BLPOP" | b"BRPOP" | b"BZPOPMIN" | b"BZPOPMAX" => KeySpec::Range {
+            first: 1,
+            skip_last: 1,
+            step: 1,
+        },
+        b"RENAME" | b"RENAMENX" | b"COPY" | b"SMOVE" | b"LMOVE" | b"BLMOVE" | b"RPOPLPUSH"
+        | b"BRPOPLPUSH" | b"GEOSEARCHSTORE" | b"ZRANGESTORE" => KeySpec::Positions(&[1, 2]),
+        // The subcommand comes first, as in `XGROUP CREATE <key> <group> <id>`
+        b"XGROUP" => KeySpec::Positions(&[2]),
+        b"EVAL" | b"EVALSHA" | b"EVAL_RO" | b"EVALSHA_RO" | b"FCALL" | b"FCALL_RO" => {
+            KeySpec::NumKeys { index: 2 }
+        }
+        b"ZUNION" | b"ZINTER" | b"ZDIFF" | b"ZINTERCARD" | b"SINTERCARD" | b"LMPOP" | b"ZMPOP" => {
+            KeySpec::NumKeys { index: 1 }
+        }
+        b"BLMPOP" | b"BZMPOP" => KeySpec::NumKeys { index: 2 },
+        b"ZUNIONSTORE" | b"ZINTERSTORE" | b"ZDIFFSTORE" => KeySpec::DestinationNumKeys,
+        b"XREAD" | b"XREADGROUP" => KeySpec::Streams,
+        _ => KeySpec::None,
+    }
+}
+
+/// The arguments of a command, none if any of them is not a string as the
+/// key positions would not line up anymore
+fn arguments(command: &RespType) -> Vec<&[u8]> {
+    match command {
+        RespType::Array(args) => args
+            .iter()
+            .map(|arg| match arg {
+                RespType::BulkString(arg) => Some(arg.as_slice()),
+                RespType::SimpleString(arg) => Some(arg.as_bytes()),
+                _ => None,
+            })
+            .collect::<Option<_>>()
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+fn num_keys<'a>(args: &[&'a [u8]], index: usize) -> Vec<&'a [u8]> {
+    let count = args
+        .get(index)
+        .and_then(|count| std::str::from_utf8(count).ok())
+        .and_then(|count| count.parse::<usize>().ok())
+        .unwrap_or(0);
+    args.iter().skip(index + 1).take(count).copied().collect()
+}
+
+/// Extracts the key arguments of a [`RespType::command`] array, for common
+/// commands. Commands without keys, that are not known, or that have
+/// arguments other than strings give no keys.
+pub fn command_keys(command: &RespType) -> Vec<&[u8]> {
+    let args = arguments(command);
+    let name = match args.first() {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+
+    match key_spec(name) {
+        KeySpec::None => Vec::new(),
+        KeySpec::Range {
+            first,
+            skip_last,
+            step,
+        } => {
+            let end = args.len().saturating_sub(skip_last);
+            args.get(first..end)
+                .unwrap_or_default()
+                .iter()
+                .step_by(step)
+                .copied()
+                .collect()
+        }
+        KeySpec::Positions(positions) => positions
+            .iter()
+            .filter_map(|position| args.get(*position).copied())
+            .collect(),
+        KeySpec::NumKeys { index } => num_keys(&args, index),
+        KeySpec::DestinationNumKeys => {
+            let mut keys: Vec<&[u8]> = args.get(1).copied().into_iter().collect();
+            keys.extend(num_keys(&args, 2));
+            keys
+        }
+        KeySpec::Streams => {
+            let streams = args
+                .iter()
+                .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"));
+            match streams {
+                Some(index) => {
+                    let rest = &args[index + 1..];
+                    rest[..rest.len() / 2].to_vec()
+                }
+                None => Vec::new(),
+            }
+        }
+    }
+}
+
+/// Hash slot that a command must be sent to, or `None` if it has no keys.
+/// Returns a [`ClientError::CrossSlotError`] if its keys are in more than
+/// one slot.
+pub fn command_slot(command: &RespType) -> ClientResult<Option<u16>> {
+    let mut slot = None;
+    for key in command_keys(command) {
+        let key_slot = key_slot(key);
+        match slot {
+            Some(slot) if slot != key_slot => return Err(ClientError::CrossSlotError),
+            _ => slot = Some(key_slot),
+        }
+    }
+    Ok(slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> RespType {
+        RespType::command(args.iter().map(|arg| arg.as_bytes().to_vec()).collect())
+    }
+
+    mod key_slot {
+        use super::*;
+
+        #[test]
+        fn checksum() {
+            assert_eq!(crc16(b"123456789"), 0x31C3);
+        }
+
+        #[test]
+        fn keys() {
+            assert_eq!(key_slot(b""), 0);
+            assert_eq!(key_slot(b"foo"), 12182);
+            assert_eq!(key_slot(b"bar"), 5061);
+            assert_eq!(key_slot(b"key"), 12539);
+        }
+
+        #[test]
+        fn hashtag() {
+            assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+            assert_eq!(key_slot(b"{user1000}.followers"), key_slot(b"user1000"));
+            assert_eq!(key_slot(b"foo{bar}{zap}"), key_slot(b"bar"));
+        }
+
+        #[test]
+        fn empty_hashtag() {
+            assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % SLOTS);
+            assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+            assert_eq!(key_slot(b"foo{bar"), crc16(b"foo{bar") % SLOTS);
+        }
+    }
+
+    mod command_keys {
+        use super::*;
+
+        fn keys(args: &[&str]) -> Vec<Vec<u8>> {
+            command_keys(&command(args))
+                .into_iter()
+                .map(<[u8]>::to_vec)
+                .collect()
+        }
+
+        fn expected(keys: &[&str]) -> Vec<Vec<u8>> {
+            keys.iter().map(|key| key.as_bytes().to_vec()).collect()
+        }
+
+        #[test]
+        fn single() {
+            assert_eq!(keys(&["GET", "a"]), expected(&["a"]));
+            assert_eq!(keys(&["set", "a", "1", "EX", "10"]), expected(&["a"]));
+            assert_eq!(keys(&["HSET", "h", "f", "v"]), expected(&["h"]));
+        }
+
+        #[test]
+        fn multiple() {
+            assert_eq!(keys(&["DEL", "a", "b", "c"]), expected(&["a", "b", "c"]));
+            assert_eq!(keys(&["MSET", "a", "1", "b", "2"]), expected(&["a", "b"]));
+            assert_eq!(keys(&["BLPOP", "a", "b", "0"]), expected(&["a", "b"]));
+            assert_eq!(keys(&["RENAME", "a", "b"]), expected(&["a", "b"]));
+        }
+
+        #[test]
+        fn num_keys() {
+            assert_eq!(
+                keys(&["EVAL", "return 1", "2", "a", "b", "arg"]),
+                expected(&["a", "b"])
+            );
+            assert_eq!(
+                keys(&["ZUNIONSTORE", "d", "2", "a", "b", "WEIGHTS", "1", "2"]),
+                expected(&["d", "a", "b"])
+            );
+            assert_eq!(keys(&["EVAL", "return 1", "0"]), expected(&[]));
+        }
+
+        #[test]
+        fn streams() {
+            assert_eq!(
+                keys(&["XREAD", "COUNT", "2", "STREAMS", "a", "b", "0", "0"]),
+                expected(&["a", "b"])
+            );
+            assert_eq!(
+                keys(&["XREADGROUP", "GROUP", "g", "c", "streams", "a", ">"]),
+                expected(&["a"])
+            );
+        }
+
+        #[test]
+        fn subcommand() {
+            assert_eq!(
+                keys(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]),
+                expected(&["s"])
+            );
+            assert_eq!(keys(&["XGROUP", "DESTROY", "s", "g"]), expected(&["s"]));
+            assert_eq!(keys(&["XGROUP", "HELP"]), expected(&[]));
+        }
+
+        #[test]
+        fn keyless() {
+            assert_eq!(keys(&["PING"]), expected(&[]));
+            assert_eq!(keys(&["UNKNOWN", "a"]), expected(&[]));
+            assert_eq!(keys(&[]), expected(&[]));
+            assert!(command_keys(&RespType::Integer(1)).is_empty());
+        }
+
+        #[test]
+        fn non_string_arguments() {
+            // Skipping the integer would take "2" as the key
+            let command = RespType::Array(vec![
+                RespType::BulkString("MSET".into()),
+                RespType::Integer(1),
+                RespType::BulkString("2".into()),
+            ]);
+            assert!(command_keys(&command).is_empty());
+        }
+    }
+
+    mod command_slot {
+        use super::*;
+
+        #[test]
+        fn same_slot() -> ClientResult<()> {
+            let slot = command_slot(&command(&["MGET", "{user}.a", "{user}.b"]))?;
+            assert_eq!(slot, Some(key_slot(b"user")));
+            assert_eq!(command_slot(&command(&["PING"]))?, None);
+            Ok(())
+        }
+
+        #[test]
+        fn cross_slot() {
+            let result = command_slot(&command(&["MGET", "foo", "bar"]));
+            assert!(matches!(result, Err(ClientError::CrossSlotError)));
+        }
+    }
+}
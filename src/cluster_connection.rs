@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+use std::io;
+
+use super::cluster::command_slot;
+use super::connection::{check_ok, check_reply};
+use super::error::{ClientError, ClientResult, ErrorCode};
+use super::Connection;
+use super::ConnectionConfig;
+use super::RespType;
+
+/// Default for [`ClusterConfig::max_redirects`]
+const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Settings used by a [`ClusterConnection`]
+#[derive(Clone, Debug)]
+pub struct ClusterConfig {
+    /// Settings for the connection to each node
+    pub connection: ConnectionConfig,
+    /// How many `MOVED` and `ASK` redirects to follow for one command
+    pub max_redirects: usize,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            connection: ConnectionConfig::default(),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+        }
+    }
+}
+
+/// A range of hash slots and the address of the primary serving them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotRange {
+    /// First slot of the range
+    pub start: u16,
+    /// Last slot of the range, inclusive
+    pub end: u16,
+    /// Address of the node, as `host:port`
+    pub addr: String,
+}
+
+/// A blocking client for a Redis Cluster, each command is sent to the node
+/// serving the hash slot of its keys.
+///
+/// The slot map is loaded with `CLUSTER SHARDS`, falling back to
+/// `CLUSTER SLOTS` on servers older than Redis 7. A `MOVED` reply reloads the
+/// map, an `ASK` reply sends the command once to the other node after
+/// `ASKING`. Connections to the nodes are opened as they are needed.
+pub struct ClusterConnection {
+    config: ClusterConfig,
+    seeds: Vec<String>,
+    slots: Vec<SlotRange>,
+    connections: HashMap<String, Connection>,
+}
+
+impl ClusterConnection {
+    /// Loads the slot map from the first of the seed nodes that answers
+    pub fn connect<S: AsRef<str>>(seeds: &[S], config: &ClusterConfig) -> ClientResult<Self> {
+        let mut cluster = ClusterConnection {
+            config: config.clone(),
+            seeds: seeds.iter().map(|seed| seed.as_ref().to_string()).collect(),
+            slots: Vec::new(),
+            connections: HashMap::new(),
+        };
+        cluster.refresh_slots()?;
+        Ok(cluster)
+    }
+
+    /// The slot map, ordered by slot
+    pub fn slots(&self) -> &[SlotRange] {
+        &self.slots
+    }
+
+    /// Address of the node serving a slot, if the slot is covered
+    pub fn node_for_slot(&self, slot: u16) -> Option<&str> {
+        let index = self.slots.partition_point(|range| range.end < slot);
+        self.slots
+            .get(index)
+            .filter(|range| range.start <= slot)
+            .map(|range| range.addr.as_str())
+    }
+
+    /// Reloads the slot map, asking the seed nodes first and then the nodes
+    /// already known
+    pub fn refresh_slots(&mut self) -> ClientResult<()> {
+        let mut nodes = self.seeds.clone();
+        for range in &self.slots {
+            if !nodes.contains(&range.addr) {
+                nodes.push(range.addr.clone());
+            }
+        }
+
+        let mut last_error = None;
+        for addr in nodes {
+            match self.load_slots(&addr) {
+                Ok(slots) => {
+                    self.slots = slots;
+                    return Ok(());
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no cluster nodes to ask").into()
+        }))
+    }
+
+    /// Sends a command to the node serving its keys and reads the reply,
+    /// following redirects. Commands without keys go to any node. Error
+    /// replies are returned as a [`ClientError::ReplyError`].
+    pub fn command(&mut self, command: Vec<Vec<u8>>) -> ClientResult<RespType> {
+        let command = RespType::command(command);
+        let slot = command_slot(&command)?;
+        let mut addr = self.route(slot)?;
+        let mut asking = false;
+
+        for _ in 0..=self.config.max_redirects {
+            if asking {
+                check_ok(self.send(&addr, &RespType::command(vec!["ASKING".into()]))?)?;
+            }
+            let error = match self.send(&addr, &command) {
+                Err(ClientError::ReplyError(error)) => error,
+                result => return result,
+            };
+            match error.code {
+                ErrorCode::Moved { addr: target, .. } => {
+                    addr = resolve(&addr, target);
+                    asking = false;
+                    // The redirect is followed either way, a map that failed
+                    // to reload is reloaded on the next MOVED
+                    let _ = self.refresh_slots();
+                }
+                ErrorCode::Ask { addr: target, .. } => {
+                    addr = resolve(&addr, target);
+                    asking = true;
+                }
+                _ => return Err(ClientError::ReplyError(error)),
+            }
+        }
+        Err(ClientError::TooManyRedirectsError)
+    }
+
+    /// Sends a command that is expected to reply with `+OK`
+    pub fn command_ok(&mut self, command: Vec<Vec<u8>>) -> ClientResult<()> {
+        match self.command(command)? {
+            RespType::SimpleString(reply) if reply == "OK" => Ok(()),
+            other => Err(ClientError::UnexpectedReplyError(other)),
+        }
+    }
+
+    /// Picks the node for a slot, any node when there is no slot or it is not
+    /// covered, that node will redirect if needed
+    fn route(&self, slot: Option<u16>) -> ClientResult<String> {
+        slot.and_then(|slot| self.node_for_slot(slot))
+            .or_else(|| self.slots.first().map(|range| range.addr.as_str()))
+            .or_else(|| self.seeds.first().map(String::as_str))
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no cluster nodes").into())
+    }
+
+    /// Sends a command on the connection to a node, a connection that failed
+    /// is dropped so the next command reconnects. RESP3 pushes arriving before
+    /// the reply are skipped, as with [`Connection::command`].
+    fn send(&mut self, addr: &str, command: &RespType) -> ClientResult<RespType> {
+        let connection = self.connection(addr)?;
+        let result = connection
+            .send(command)
+            .and_then(|_| loop {
+                match connection.receive() {
+                    Ok(reply) if reply.is_push() => continue,
+                    result => break result,
+                }
+            })
+            .and_then(check_reply);
+        if let Err(
+            ClientError::IoError(_) | ClientError::ProtocolError(_) | ClientError::ClosedError,
+        ) = result
+        {
+            self.connections.remove(addr);
+        }
+        result
+    }
+
+    fn connection(&mut self, addr: &str) -> ClientResult<&mut Connection> {
+        if !self.connections.contains_key(addr) {
+            let connection = Connection::connect_tcp(addr, &self.config.connection)?;
+            self.connections.insert(addr.to_string(), connection);
+        }
+        Ok(self
+            .connections
+            .get_mut(addr)
+            .expect("connection was inserted"))
+    }
+
+    fn load_slots(&mut self, addr: &str) -> ClientResult<Vec<SlotRange>> {
+        let host = host(addr).to_string();
+        let shards = self.send(addr, &cluster_command("SHARDS"));
+        let (reply, slots) = match shards {
+            Ok(reply) => {
+                let slots = parse_shards(&reply, &host);
+                (reply, slots)
+            }
+            // CLUSTER SHARDS was added in Redis 7
+            Err(ClientError::ReplyError(_)) => {
+                let reply = self.send(addr, &cluster_command("SLOTS"))?;
+                let slots = parse_slots(&reply, &host);
+                (reply, slots)
+            }
+            Err(error) => return Err(error),
+        };
+        let mut slots = slots.ok_or(ClientError::UnexpectedReplyError(reply))?;
+        slots.sort_by_key(|range| range.start);
+        Ok(slots)
+    }
+}
+
+fn cluster_command(subcommand: &str) -> RespType {
+    RespType::command(vec!["CLUSTER".into(), subcommand.into()])
+}
+
+/// Host part of a `host:port` address
+fn host(addr: &str) -> &str {
+    addr.rsplit_once(':').map_or(addr, |(host, _)| host)
+}
+
+/// Joins a host and port, an empty host means the node that was asked
+fn node_addr(host: &str, port: i64, asked: &str) -> String {
+    match host {
+        "" | "?" => format!("{}:{}", asked, port),
+        host if host.contains(':') && !host.starts_with('[') => format!("[{}]:{}", host, port),
+        host => format!("{}:{}", host, port),
+    }
+}
+
+/// Redirect targets with an empty host, such as `:6380`, are on the host of
+/// the node that replied
+fn resolve(from: &str, target: String) -> String {
+    match target.strip_prefix(':') {
+        Some(port) => format!("{}:{}", host(from), port),
+        None => target,
+    }
+}
+
+fn integer(resp: &RespType) -> Option<i64> {
+    match resp {
+        RespType::Integer(integer) => Some(*integer),
+        resp => resp.as_text()?.parse().ok(),
+    }
+}
+
+fn slot(resp: &RespType) -> Option<u16> {
+    integer(resp)?.try_into().ok()
+}
+
+/// Looks up a field of a RESP3 map, or of a RESP2 array of key/value pairs
+fn field<'a>(resp: &'a RespType, name: &str) -> Option<&'a RespType> {
+    match resp {
+        RespType::Map(pairs) => pairs
+            .iter()
+            .find(|(key, _)| key.as_text() == Some(name))
+            .map(|(_, value)| value),
+        RespType::Array(elements) => elements
+            .chunks_exact(2)
+            .find(|pair| pair[0].as_text() == Some(name))
+            .map(|pair| &pair[1]),
+        _ => None,
+    }
+}
+
+/// Parses `CLUSTER SLOTS`, each entry is the slot range followed by the
+/// primary as `[host, port, id, ...]` and then its replicas
+fn parse_slots(reply: &RespType, asked: &str) -> Option<Vec<SlotRange>> {
+    let RespType::Array(entries) = reply else {
+        return None;
+    };
+    entries
+        .iter()
+        .map(|entry| match entry {
+            RespType::Array(entry) if entry.len() >= 3 => {
+                let RespType::Array(primary) = &entry[2] else {
+                    return None;
+                };
+                let host = primary.first()?.as_text()?;
+                let port = integer(primary.get(1)?)?;
+                Some(SlotRange {
+                    start: slot(&entry[0])?,
+                    end: slot(&entry[1])?,
+                    addr: node_addr(host, port, asked),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses `CLUSTER SHARDS`, each shard has a flat list of slot range bounds
+/// and its nodes, of which the primary serves the slots
+fn parse_shards(reply: &RespType, asked: &str) -> Option<Vec<SlotRange>> {
+    let RespType::Array(shards) = reply else {
+        return None;
+    };
+    let mut slots = Vec::new();
+    for shard in shards {
+        let (RespType::Array(bounds), RespType::Array(nodes)) =
+            (field(shard, "slots")?, field(shard, "nodes")?)
+        else {
+            return None;
+        };
+        if bounds.is_empty() {
+            continue;
+        }
+        let primary = nodes
+            .iter()
+            .find(|node| field(node, "role").and_then(RespType::as_text) == Some("master"))?;
+        let host = field(primary, "endpoint")
+            .or_else(|| field(primary, "ip"))
+            .and_then(RespType::as_text)?;
+        let port = integer(field(primary, "port")?)?;
+        let addr = node_addr(host, port, asked);
+        for bounds in bounds.chunks_exact(2) {
+            slots.push(SlotRange {
+                start: slot(&bounds[0])?,
+                end: slot(&bounds[1])?,
+                addr: addr.clone(),
+            });
+        }
+    }
+    Some(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::key_slot;
+    use crate::connection::tests::{serve, serve_raw};
+    use crate::ServerError;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use RespType::*;
+
+    fn bulk(data: &str) -> RespType {
+        BulkString(data.into())
+    }
+
+    fn slots_reply(ranges: &[(i64, i64, SocketAddr)]) -> RespType {
+        Array(
+            ranges
+                .iter()
+                .map(|(start, end, addr)| {
+                    Array(vec![
+                        Integer(*start),
+                        Integer(*end),
+                        Array(vec![
+                            bulk(&addr.ip().to_string()),
+                            Integer(addr.port() as i64),
+                            bulk("id"),
+                        ]),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// A seed node without `CLUSTER SHARDS`, answering `CLUSTER SLOTS` with
+    /// each of the maps in turn and repeating the last
+    fn seed(maps: Vec<Vec<(i64, i64, SocketAddr)>>) -> SocketAddr {
+        let mut loaded = 0;
+        serve(move |command| match command[1].as_slice() {
+            b"SLOTS" => {
+                let map = &maps[loaded.min(maps.len() - 1)];
+                loaded += 1;
+                slots_reply(map)
+            }
+            _ => Error("ERR unknown subcommand 'SHARDS'".into()),
+        })
+    }
+
+    /// A node answering `GET` with its name
+    fn node(name: &'static str) -> SocketAddr {
+        serve(move |_| bulk(name))
+    }
+
+    fn get(key: &str) -> Vec<Vec<u8>> {
+        vec!["GET".into(), key.into()]
+    }
+
+    #[test]
+    fn parse_cluster_slots() {
+        let reply = Array(vec![Array(vec![
+            Integer(0),
+            Integer(5460),
+            Array(vec![bulk(""), Integer(6379), bulk("id")]),
+            Array(vec![bulk("10.0.0.2"), Integer(6380), bulk("id")]),
+        ])]);
+        let slots = parse_slots(&reply, "10.0.0.1").unwrap();
+        assert_eq!(
+            slots,
+            vec![SlotRange {
+                start: 0,
+                end: 5460,
+                addr: "10.0.0.1:6379".into()
+            }]
+        );
+        assert_eq!(parse_slots(&Array(vec![Integer(1)]), "10.0.0.1"), None);
+    }
+
+    #[test]
+    fn parse_cluster_shards() {
+        let node = |role: &str, ip: &str, port| {
+            Map(vec![
+                (bulk("id"), bulk("id")),
+                (bulk("port"), Integer(port)),
+                (bulk("ip"), bulk(ip)),
+                (bulk("endpoint"), bulk(ip)),
+                (bulk("role"), bulk(role)),
+                (bulk("health"), bulk("online")),
+            ])
+        };
+        let reply = Array(vec![
+            Map(vec![
+                (
+                    bulk("slots"),
+                    Array(vec![Integer(0), Integer(99), Integer(200), Integer(299)]),
+                ),
+                (
+                    bulk("nodes"),
+                    Array(vec![
+                        node("replica", "10.0.0.2", 6380),
+                        node("master", "10.0.0.1", 6379),
+                    ]),
+                ),
+            ]),
+            // RESP2 servers send each shard as a flat array
+            Array(vec![
+                bulk("slots"),
+                Array(vec![Integer(100), Integer(199)]),
+                bulk("nodes"),
+                Array(vec![Array(vec![
+                    bulk("port"),
+                    Integer(6381),
+                    bulk("ip"),
+                    bulk("::1"),
+                    bulk("role"),
+                    bulk("master"),
+                ])]),
+            ]),
+        ]);
+        let slots = parse_shards(&reply, "10.0.0.1").unwrap();
+        let addrs: Vec<_> = slots
+            .iter()
+            .map(|range| (range.start, range.end, range.addr.as_str()))
+            .collect();
+        assert_eq!(
+            addrs,
+            vec![
+                (0, 99, "10.0.0.1:6379"),
+                (200, 299, "10.0.0.1:6379"),
+                (100, 199, "[::1]:6381"),
+            ]
+        );
+    }
+
+    #[test]
+    fn routing() -> ClientResult<()> {
+        let (a, b) = (node("a"), node("b"));
+        let seed = seed(vec![vec![(0, 8191, a), (8192, 16383, b)]]);
+        let mut cluster =
+            ClusterConnection::connect(&[seed.to_string()], &ClusterConfig::default())?;
+
+        assert_eq!(cluster.slots().len(), 2);
+        assert!(key_slot(b"bar") < 8192 && key_slot(b"foo") >= 8192);
+        assert_eq!(cluster.command(get("bar"))?, bulk("a"));
+        assert_eq!(cluster.command(get("foo"))?, bulk("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn moved() -> ClientResult<()> {
+        let b = node("b");
+        let a = serve(move |command| {
+            let slot = key_slot(&command[1]);
+            Error(format!("MOVED {} {}", slot, b))
+        });
+        let seed = seed(vec![
+            vec![(0, 16383, a)],
+            vec![(0, 8191, a), (8192, 16383, b)],
+        ]);
+        let mut cluster =
+            ClusterConnection::connect(&[seed.to_string()], &ClusterConfig::default())?;
+
+        assert_eq!(cluster.command(get("foo"))?, bulk("b"));
+        // The map was reloaded, so the next command goes straight to b
+        let b = b.to_string();
+        assert_eq!(cluster.node_for_slot(key_slot(b"foo")), Some(b.as_str()));
+        assert_eq!(cluster.command(get("foo"))?, bulk("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn moved_refresh_failure() -> ClientResult<()> {
+        let b = node("b");
+        let a = serve(move |command| {
+            let slot = key_slot(&command[1]);
+            Error(format!("MOVED {} {}", slot, b))
+        });
+        let mut loaded = false;
+        let seed = serve(move |command| match command[1].as_slice() {
+            b"SLOTS" if !std::mem::replace(&mut loaded, true) => slots_reply(&[(0, 16383, a)]),
+            _ => Error("CLUSTERDOWN The cluster is down".into()),
+        });
+        let mut cluster =
+            ClusterConnection::connect(&[seed.to_string()], &ClusterConfig::default())?;
+        assert_eq!(cluster.command(get("foo"))?, bulk("b"));
+        Ok(())
+    }
+
+    #[test]
+    fn pushes() -> ClientResult<()> {
+        // Every reply comes after a pub/sub message
+        let a = serve_raw(|command| {
+            let mut reply = Push(vec![bulk("message"), bulk("news"), bulk("hi")]).as_bytes();
+            reply.extend(BulkString(command[1].clone()).as_bytes());
+            reply
+        });
+        let seed = seed(vec![vec![(0, 16383, a)]]);
+        let mut cluster =
+            ClusterConnection::connect(&[seed.to_string()], &ClusterConfig::default())?;
+        assert_eq!(cluster.command(get("foo"))?, bulk("foo"));
+        assert_eq!(cluster.command(get("bar"))?, bulk("bar"));
+        Ok(())
+    }
+
+    #[test]
+    fn ask() -> ClientResult<()> {
+        let mut asking = false;
+        let b = serve(move |command| match command[0].as_slice() {
+            b"ASKING" => {
+                asking = true;
+                SimpleString("OK".into())
+            }
+            _ if std::mem::take(&mut asking) => bulk("b"),
+            _ => Error("ERR expected ASKING first".into()),
+        });
+        let a = serve(move |command| Error(format!("ASK {} {}", key_slot(&command[1]), b)));
+        let seed = seed(vec![vec![(0, 16383, a)]]);
+        let mut cluster =
+            ClusterConnection::connect(&[seed.to_string()], &ClusterConfig::default())?;
+
+        assert_eq!(cluster.command(get("foo"))?, bulk("b"));
+        // ASK only redirects the one command, the map still points at a
+        let a = a.to_string();
+        assert_eq!(cluster.node_for_slot(key_slot(b"foo")), Some(a.as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_limit() -> ClientResult<()> {
+        let b_addr = Arc::new(Mutex::new(None::<SocketAddr>));
+        let moved_to = b_addr.clone();
+        let a = serve(move |_| Error(format!("MOVED 1 {}", moved_to.lock().unwrap().unwrap())));
+        let b = serve(move |_| Error(format!("MOVED 1 {}", a)));
+        *b_addr.lock().unwrap() = Some(b);
+        let seed = seed(vec![vec![(0, 16383, a)]]);
+        let config = ClusterConfig {
+            max_redirects: 3,
+            ..Default::default()
+        };
+        let mut cluster = ClusterConnection::connect(&[seed.to_string()], &config)?;
+        assert!(matches!(
+            cluster.command(get("foo")),
+            Err(ClientError::TooManyRedirectsError)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn other_errors() -> ClientResult<()> {
+        let a = serve(|_| {
+            Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+        });
+        let seed = seed(vec![vec![(0, 16383, a)]]);
+        let mut cluster =
+            ClusterConnection::connect(&[seed.to_string()], &ClusterConfig::default())?;
+        match cluster.command(get("foo")) {
+            Err(ClientError::ReplyError(ServerError {
+                code: ErrorCode::WrongType,
+                ..
+            })) => {}
+            other => panic!("expected a WRONGTYPE error, got {:?}", other),
+        }
+        assert!(matches!(
+            cluster.command(vec!["MGET".into(), "foo".into(), "bar".into()]),
+            Err(ClientError::CrossSlotError)
+        ));
+        Ok(())
+    }
+}
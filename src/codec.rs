@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::io;
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::error::{RespError, Result};
+use super::RespConfig;
+use super::RespParser;
+use super::RespType;
+
+/// A [`tokio_util`] codec for framing a stream of RESP types, so that
+/// `Framed<TcpStream, RespCodec>` can be used as a stream of [`RespType`]
+/// values and a sink for encoding them.
+///
+/// Decoding is done by a [`RespParser`], so partial reads keep their parsing
+/// state and the [`RespConfig`] limits apply to the framed stream.
+pub struct RespCodec {
+    parser: RespParser,
+    pending: VecDeque<RespType>,
+}
+
+impl Default for RespCodec {
+    fn default() -> Self {
+        Self::new(RespConfig::default())
+    }
+}
+
+impl RespCodec {
+    /// Creates a new instance, can use [`RespCodec::default`] for common setups
+    pub fn new(config: RespConfig) -> Self {
+        RespCodec {
+            parser: RespParser::new(config),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Configuration structure for memory limits
+    pub fn config(&self) -> &RespConfig {
+        &self.parser.config
+    }
+
+    /// Mutable access to the configuration, changes apply to the next read
+    pub fn config_mut(&mut self) -> &mut RespConfig {
+        &mut self.parser.config
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = RespType;
+    type Error = RespError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RespType>> {
+        if !src.is_empty() {
+            // The parser keeps its own copy of incomplete data
+            let results = self.parser.read(src);
+            src.clear();
+            self.pending.extend(results?);
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<RespType>> {
+        match self.decode(src)? {
+            Some(item) => Ok(Some(item)),
+            None if self.parser.is_empty() => Ok(None),
+            None => {
+                let error = "stream ended with an incomplete RESP type";
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, error).into())
+            }
+        }
+    }
+}
+
+impl Encoder<RespType> for RespCodec {
+    type Error = RespError;
+
+    fn encode(&mut self, item: RespType, dst: &mut BytesMut) -> Result<()> {
+        dst.reserve(item.encoded_len());
+        item.encode_to(dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParserError;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::Framed;
+    use RespType::*;
+
+    #[test]
+    fn decode_multiple() -> Result<()> {
+        let mut codec = RespCodec::default();
+        let mut buffer = BytesMut::from(&b"+OK\r\n:1\r\n$3\r\nfoo"[..]);
+        assert_eq!(codec.decode(&mut buffer)?, Some(SimpleString("OK".into())));
+        assert!(buffer.is_empty());
+        assert_eq!(codec.decode(&mut buffer)?, Some(Integer(1)));
+        assert_eq!(codec.decode(&mut buffer)?, None);
+
+        buffer.extend_from_slice(b"\r\n");
+        assert_eq!(codec.decode(&mut buffer)?, Some(BulkString("foo".into())));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_eof_incomplete() {
+        let mut codec = RespCodec::default();
+        let mut buffer = BytesMut::from(&b"$3\r\nfo"[..]);
+        let error = codec.decode_eof(&mut buffer).unwrap_err();
+        assert!(matches!(error.kind(), ParserError::IoError(_)));
+    }
+
+    #[test]
+    fn size_exceeded() {
+        let mut codec = RespCodec::new(RespConfig::new(4, 1024));
+        let mut buffer = BytesMut::from(&b"$5\r\nhello\r\n"[..]);
+        let error = codec.decode(&mut buffer).unwrap_err();
+        assert!(matches!(error.kind(), ParserError::SizeExceededError));
+    }
+
+    #[test]
+    fn encode() -> Result<()> {
+        let mut codec = RespCodec::default();
+        let mut buffer = BytesMut::new();
+        codec.encode(
+            RespType::command(vec!["GET".into(), "key".into()]),
+            &mut buffer,
+        )?;
+        assert_eq!(&buffer[..], b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn framed() -> Result<()> {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut framed = Framed::new(client, RespCodec::default());
+
+        framed.send(RespType::command(vec!["PING".into()])).await?;
+        let mut request = [0; 14];
+        server.read_exact(&mut request).await?;
+        assert_eq!(&request, b"*1\r\n$4\r\nPING\r\n");
+
+        server.write_all(b"+PONG\r\n%1\r\n+a\r\n").await?;
+        assert_eq!(
+            framed.next().await.transpose()?,
+            Some(SimpleString("PONG".into()))
+        );
+        server.write_all(b"#t\r\n").await?;
+        drop(server);
+        let expected = Map(vec![(SimpleString("a".into()), Boolean(true))]);
+        assert_eq!(framed.next().await.transpose()?, Some(expected));
+        assert_eq!(framed.next().await.transpose()?, None);
+        Ok(())
+    }
+}
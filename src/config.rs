@@ -1,12 +1,42 @@
 /// Structure for memory limits per-parser
+#[derive(Clone, Debug)]
 pub struct RespConfig {
     /// This is the maximum size of an individual RESP type
     pub max_resp_size: usize,
     /// This is the max size of the internal buffer
     pub max_buffer_size: usize,
+    /// When set, RESP3 attributes are dropped and only the reply they describe
+    /// is returned, rather than a [`RespType::Attribute`](crate::RespType::Attribute)
+    pub discard_attributes: bool,
+    /// This is the maximum nesting depth of aggregate types, an attribute
+    /// also counts as a level for the reply it comes with
+    pub max_depth: usize,
+    /// Protocol version the data is expected in, RESP3 accepts RESP2 types as
+    /// well while RESP2 rejects the RESP3 types
+    pub protocol: Protocol,
+    /// When set, a line that does not start with a type token is read as an
+    /// inline command, such as `SET a "b c"`, and returned as an array of bulk
+    /// strings split the way redis-server does. Lines are limited to 64KB, or
+    /// `max_resp_size` if smaller. Meant for servers.
+    pub inline_commands: bool,
+}
+
+/// RESP protocol versions, as negotiated with `HELLO`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2 = 2,
+    Resp3 = 3,
+}
+
+impl Protocol {
+    /// Checks if a type token is part of this protocol
+    pub(crate) fn allows(self, token: u8) -> bool {
+        self == Protocol::Resp3 || matches!(token, b'+' | b'-' | b':' | b'$' | b'*')
+    }
 }
 
 const DEFAULT_MAX: usize = 512 * 1024 * 1024;
+const DEFAULT_MAX_DEPTH: usize = 128;
 
 impl Default for RespConfig {
     fn default() -> Self {
@@ -19,6 +49,10 @@ impl RespConfig {
         RespConfig {
             max_resp_size,
             max_buffer_size,
+            discard_attributes: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            protocol: Protocol::Resp3,
+            inline_commands: false,
         }
     }
 }
@@ -0,0 +1,760 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+
+use super::error::{ClientError, ClientResult, ErrorCode, ServerError};
+use super::hello::hello_command;
+use super::Pipeline;
+use super::RespParser;
+use super::RespType;
+use super::ServerInfo;
+use super::{Protocol, RespConfig};
+use super::{Transaction, TransactionOutcome};
+
+const READ_SIZE: usize = 16 * 1024;
+
+/// Settings used when opening a [`Connection`]
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionConfig {
+    /// Username sent with `AUTH`, only used when a password is set
+    pub username: Option<String>,
+    /// When set, `AUTH` is sent on connect
+    pub password: Option<String>,
+    /// When not 0, `SELECT` is sent on connect
+    pub database: usize,
+    /// Timeout for establishing a TCP connection
+    pub connect_timeout: Option<Duration>,
+    /// Timeout for each read from the server
+    pub read_timeout: Option<Duration>,
+    /// Timeout for each write to the server
+    pub write_timeout: Option<Duration>,
+    /// Memory limits for the parser reading replies
+    pub resp: RespConfig,
+    /// When set, the connection starts with `HELLO` to negotiate the
+    /// protocol, authenticating and setting the client name with it
+    pub protocol: Option<Protocol>,
+    /// Name set with `CLIENT SETNAME`, or with `HELLO` when a protocol is set
+    pub client_name: Option<String>,
+}
+
+impl ConnectionConfig {
+    /// Commands sent when connecting, each expected to reply with `+OK`.
+    /// Authentication and the client name are left out when they were
+    /// already sent with `HELLO`.
+    pub(crate) fn setup_commands(&self, hello: bool) -> Vec<Vec<Vec<u8>>> {
+        let mut commands = Vec::new();
+        if let (Some(password), false) = (&self.password, hello) {
+            let mut command = vec!["AUTH".into()];
+            if let Some(username) = &self.username {
+                command.push(username.clone().into_bytes());
+            }
+            command.push(password.clone().into_bytes());
+            commands.push(command);
+        }
+        if let (Some(name), false) = (&self.client_name, hello) {
+            commands.push(vec![
+                "CLIENT".into(),
+                "SETNAME".into(),
+                name.clone().into_bytes(),
+            ]);
+        }
+        if self.database != 0 {
+            commands.push(vec!["SELECT".into(), self.database.to_string().into()]);
+        }
+        commands
+    }
+}
+
+/// A TCP or Unix domain socket stream
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub(crate) fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(std::net::Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(std::net::Shutdown::Both),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buffer),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A blocking connection to a Redis server, commands are sent as
+/// [`RespType::command`] frames and replies are read with a [`RespParser`]
+pub struct Connection {
+    stream: Stream,
+    parser: RespParser,
+    replies: VecDeque<RespType>,
+    server_info: Option<ServerInfo>,
+    /// Set after an IO or protocol error, once replies can no longer be
+    /// matched up with commands
+    broken: bool,
+}
+
+impl Connection {
+    /// Connects over TCP, then authenticates and selects the database as set
+    /// in the config
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A, config: &ConnectionConfig) -> ClientResult<Self> {
+        let stream = match config.connect_timeout {
+            None => TcpStream::connect(addr)?,
+            Some(timeout) => {
+                let mut result = Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "address resolved to nothing",
+                ));
+                for addr in addr.to_socket_addrs()? {
+                    result = TcpStream::connect_timeout(&addr, timeout);
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                result?
+            }
+        };
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(config.read_timeout)?;
+        stream.set_write_timeout(config.write_timeout)?;
+        Self::setup(Stream::Tcp(stream), config)
+    }
+
+    /// Connects over a Unix domain socket, then authenticates and selects the
+    /// database as set in the config
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P, config: &ConnectionConfig) -> ClientResult<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(config.read_timeout)?;
+        stream.set_write_timeout(config.write_timeout)?;
+        Self::setup(Stream::Unix(stream), config)
+    }
+
+    fn setup(stream: Stream, config: &ConnectionConfig) -> ClientResult<Self> {
+        let mut connection = Connection {
+            stream,
+            parser: RespParser::new(config.resp.clone()),
+            replies: VecDeque::new(),
+            server_info: None,
+            broken: false,
+        };
+
+        if let Some(protocol) = config.protocol {
+            connection.hello(protocol, config)?;
+        }
+        for command in config.setup_commands(config.protocol.is_some()) {
+            connection.command_ok(command)?;
+        }
+        Ok(connection)
+    }
+
+    /// Sends `HELLO`, authenticating and setting the client name as set in
+    /// the config, and sets the parser up for the negotiated protocol. If the
+    /// server replies `NOPROTO`, RESP2 is asked for instead.
+    pub fn hello(
+        &mut self,
+        protocol: Protocol,
+        config: &ConnectionConfig,
+    ) -> ClientResult<ServerInfo> {
+        // The reply to `HELLO 3` is a RESP3 map, even when RESP2 was in use
+        let previous = std::mem::replace(&mut self.parser.config.protocol, Protocol::Resp3);
+        let reply = match self.command(hello_command(protocol, config)) {
+            Err(ClientError::ReplyError(ServerError {
+                code: ErrorCode::NoProto,
+                ..
+            })) if protocol != Protocol::Resp2 => {
+                self.command(hello_command(Protocol::Resp2, config))
+            }
+            result => result,
+        };
+        let info = match reply.and_then(ServerInfo::try_from) {
+            Ok(info) => info,
+            Err(error) => {
+                self.parser.config.protocol = previous;
+                return Err(error);
+            }
+        };
+        self.parser.config.protocol = info.proto;
+        self.server_info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// What the server reported in reply to `HELLO`, if it was sent
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.server_info.as_ref()
+    }
+
+    /// Sends a command and reads its reply, error replies are returned as a
+    /// [`ClientError::ReplyError`]. RESP3 pushes arriving before the reply
+    /// are skipped.
+    pub fn command(&mut self, command: Vec<Vec<u8>>) -> ClientResult<RespType> {
+        self.send(&RespType::command(command))?;
+        loop {
+            let reply = self.receive()?;
+            if !reply.is_push() {
+                return check_reply(reply);
+            }
+        }
+    }
+
+    /// Sends a command that is expected to reply with `+OK`
+    pub fn command_ok(&mut self, command: Vec<Vec<u8>>) -> ClientResult<()> {
+        check_ok(self.command(command)?)
+    }
+
+    /// Sends all commands of the pipeline in one write, then reads one reply
+    /// for each. Error replies only fail their own command.
+    pub fn pipeline(&mut self, pipeline: &Pipeline) -> ClientResult<Vec<ClientResult<RespType>>> {
+        self.guarded(|connection| Ok(connection.stream.write_all(&pipeline.encode())?))?;
+        let mut results = Vec::with_capacity(pipeline.len());
+        while results.len() < pipeline.len() {
+            let reply = self.receive()?;
+            if !reply.is_push() {
+                results.push(check_reply(reply));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Runs the commands of the transaction between `MULTI` and `EXEC`
+    pub fn transaction(&mut self, transaction: &Transaction) -> ClientResult<TransactionOutcome> {
+        let replies = self.pipeline(&transaction.pipeline())?;
+        transaction.unpack(replies)
+    }
+
+    /// Writes a RESP type to the server without waiting for a reply
+    pub fn send(&mut self, resp: &RespType) -> ClientResult<()> {
+        let mut buffer = Vec::with_capacity(resp.encoded_len());
+        resp.encode(&mut buffer)?;
+        self.guarded(|connection| Ok(connection.stream.write_all(&buffer)?))
+    }
+
+    /// Reads the next RESP type sent by the server, error replies are returned
+    /// as they are
+    pub fn receive(&mut self) -> ClientResult<RespType> {
+        self.guarded(|connection| {
+            let mut buffer = [0; READ_SIZE];
+            loop {
+                if let Some(reply) = connection.replies.pop_front() {
+                    return Ok(reply);
+                }
+                let read = connection.stream.read(&mut buffer)?;
+                if read == 0 {
+                    return Err(ClientError::ClosedError);
+                }
+                connection
+                    .replies
+                    .extend(connection.parser.read(&buffer[..read])?);
+            }
+        })
+    }
+
+    /// Returns the next RESP type if it has already arrived, without waiting
+    /// for the server
+    pub fn try_receive(&mut self) -> ClientResult<Option<RespType>> {
+        self.guarded(|connection| {
+            if let Some(reply) = connection.replies.pop_front() {
+                return Ok(Some(reply));
+            }
+            let mut buffer = [0; READ_SIZE];
+            connection.stream.set_nonblocking(true)?;
+            let result = connection.stream.read(&mut buffer);
+            connection.stream.set_nonblocking(false)?;
+            match result {
+                Ok(0) => Err(ClientError::ClosedError),
+                Ok(read) => {
+                    let replies = connection.parser.read(&buffer[..read])?;
+                    connection.replies.extend(replies);
+                    Ok(connection.replies.pop_front())
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                Err(error) => Err(error.into()),
+            }
+        })
+    }
+
+    /// Runs an operation on the stream. After an IO or protocol error, such
+    /// as a read timeout, a late reply would be taken for the reply to the
+    /// next command, so the connection is marked broken and later calls fail
+    /// with [`ClientError::ClosedError`].
+    fn guarded<T, F>(&mut self, operation: F) -> ClientResult<T>
+    where
+        F: FnOnce(&mut Self) -> ClientResult<T>,
+    {
+        if self.broken {
+            return Err(ClientError::ClosedError);
+        }
+        let result = operation(self);
+        if let Err(
+            ClientError::IoError(_) | ClientError::ProtocolError(_) | ClientError::ClosedError,
+        ) = result
+        {
+            self.broken = true;
+        }
+        result
+    }
+}
+
+/// Checks for an `+OK` reply
+pub(crate) fn check_ok(reply: RespType) -> ClientResult<()> {
+    match reply {
+        RespType::SimpleString(reply) if reply == "OK" => Ok(()),
+        other => Err(ClientError::UnexpectedReplyError(other)),
+    }
+}
+
+/// Turns error replies into a [`ClientError::ReplyError`]
+pub(crate) fn check_reply(reply: RespType) -> ClientResult<RespType> {
+    match reply.server_error() {
+        Some(error) => Err(ClientError::ReplyError(error)),
+        None => Ok(reply),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ParserError;
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+    use RespType::*;
+
+    /// Serves a single connection, replying to each command with the handler.
+    /// The replies to all commands in one read are written together.
+    pub(crate) fn serve<F>(mut handler: F) -> SocketAddr
+    where
+        F: FnMut(Vec<Vec<u8>>) -> RespType + Send + 'static,
+    {
+        serve_raw(move |command| handler(command).as_bytes())
+    }
+
+    /// Like [`serve`], but the handler returns the encoded reply, which can be
+    /// any number of frames
+    pub(crate) fn serve_raw<F>(mut handler: F) -> SocketAddr
+    where
+        F: FnMut(Vec<Vec<u8>>) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut parser = RespParser::default();
+            let mut buffer = [0; 1024];
+            loop {
+                let read = match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => return,
+                    Ok(read) => read,
+                };
+                let mut replies = Vec::new();
+                for command in parser.read(&buffer[..read]).unwrap() {
+                    let command = match command {
+                        Array(args) => args
+                            .into_iter()
+                            .map(|arg| match arg {
+                                BulkString(arg) => arg,
+                                other => panic!("expected bulk string, got {:?}", other),
+                            })
+                            .collect(),
+                        other => panic!("expected command, got {:?}", other),
+                    };
+                    replies.extend(handler(command));
+                }
+                if stream.write_all(&replies).is_err() {
+                    return;
+                }
+            }
+        });
+        addr
+    }
+
+    /// The reply to `HELLO`, a map for RESP3 or a flat array for RESP2
+    pub(crate) fn hello_reply(protocol: Protocol) -> RespType {
+        let pairs = vec![
+            (BulkString("server".into()), BulkString("redis".into())),
+            (BulkString("version".into()), BulkString("7.2.0".into())),
+            (BulkString("proto".into()), Integer(protocol as i64)),
+            (BulkString("id".into()), Integer(7)),
+            (BulkString("mode".into()), BulkString("standalone".into())),
+            (BulkString("role".into()), BulkString("master".into())),
+            (BulkString("modules".into()), Array(vec![])),
+        ];
+        match protocol {
+            Protocol::Resp3 => Map(pairs),
+            Protocol::Resp2 => Array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect(),
+            ),
+        }
+    }
+
+    fn ok() -> RespType {
+        SimpleString("OK".into())
+    }
+
+    #[test]
+    fn command() -> ClientResult<()> {
+        let addr = serve(|command| match command[0].as_slice() {
+            b"PING" => SimpleString("PONG".into()),
+            b"GET" => BulkString(command[1].clone()),
+            _ => Error("ERR unknown command".into()),
+        });
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        assert_eq!(
+            connection.command(vec!["PING".into()])?,
+            SimpleString("PONG".into())
+        );
+        assert_eq!(
+            connection.command(vec!["GET".into(), "key".into()])?,
+            BulkString("key".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn error_reply() -> ClientResult<()> {
+        let addr = serve(|_| Error("ERR unknown command".into()));
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        match connection.command(vec!["NOPE".into()]) {
+            Err(ClientError::ReplyError(error)) => {
+                assert_eq!(error.to_string(), "ERR unknown command")
+            }
+            other => panic!("expected a reply error, got {:?}", other),
+        }
+
+        // The connection is still usable after an error reply
+        connection.send(&RespType::command(vec!["NOPE".into()]))?;
+        assert_eq!(connection.receive()?, Error("ERR unknown command".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline() -> ClientResult<()> {
+        let addr = serve(|command| match command[0].as_slice() {
+            b"ECHO" => BulkString(command[1].clone()),
+            _ => Error("ERR unknown command".into()),
+        });
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        let mut pipeline = Pipeline::new();
+        pipeline
+            .command(vec!["ECHO".into(), "a".into()])
+            .command(vec!["NOPE".into()])
+            .command(vec!["ECHO".into(), "b".into()]);
+
+        let results = connection.pipeline(&pipeline)?;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &BulkString("a".into()));
+        assert!(matches!(results[1], Err(ClientError::ReplyError(_))));
+        assert_eq!(results[2].as_ref().unwrap(), &BulkString("b".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn transaction() -> ClientResult<()> {
+        let mut queued = Vec::new();
+        let addr = serve(move |command| match command[0].as_slice() {
+            b"MULTI" => ok(),
+            b"EXEC" if queued.len() == 1 => NullArray,
+            b"EXEC" => Array(std::mem::take(&mut queued)),
+            _ => {
+                queued.push(BulkString(command[1].clone()));
+                SimpleString("QUEUED".into())
+            }
+        });
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        let mut transaction = Transaction::new();
+        transaction
+            .command(vec!["ECHO".into(), "a".into()])
+            .command(vec!["ECHO".into(), "b".into()]);
+
+        match connection.transaction(&transaction)? {
+            TransactionOutcome::Committed(results) => {
+                assert_eq!(results[0].as_ref().unwrap(), &BulkString("a".into()));
+                assert_eq!(results[1].as_ref().unwrap(), &BulkString("b".into()));
+            }
+            other => panic!("expected a committed transaction, got {:?}", other),
+        }
+
+        let mut transaction = Transaction::new();
+        transaction.command(vec!["ECHO".into(), "c".into()]);
+        let outcome = connection.transaction(&transaction)?;
+        assert!(matches!(outcome, TransactionOutcome::Aborted));
+        Ok(())
+    }
+
+    #[test]
+    fn auth_select() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [auth, user, pass] if auth == b"AUTH" && user == b"user" && pass == b"secret" => ok(),
+            [select, db] if select == b"SELECT" && db == b"2" => ok(),
+            [ping] if ping == b"PING" => SimpleString("PONG".into()),
+            _ => Error("ERR unexpected".into()),
+        });
+        let config = ConnectionConfig {
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            database: 2,
+            ..Default::default()
+        };
+        let mut connection = Connection::connect_tcp(addr, &config)?;
+        assert_eq!(
+            connection.command(vec!["PING".into()])?,
+            SimpleString("PONG".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn client_name() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [client, setname, name]
+                if client == b"CLIENT" && setname == b"SETNAME" && name == b"app" =>
+            {
+                ok()
+            }
+            _ => Error("ERR unexpected".into()),
+        });
+        let config = ConnectionConfig {
+            client_name: Some("app".into()),
+            ..Default::default()
+        };
+        let connection = Connection::connect_tcp(addr, &config)?;
+        assert!(connection.server_info().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn hello() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [hello, proto, auth, user, pass, setname, name]
+                if hello == b"HELLO"
+                    && proto == b"3"
+                    && auth == b"AUTH"
+                    && user == b"user"
+                    && pass == b"secret"
+                    && setname == b"SETNAME"
+                    && name == b"app" =>
+            {
+                hello_reply(Protocol::Resp3)
+            }
+            [select, db] if select == b"SELECT" && db == b"1" => ok(),
+            [ping] if ping == b"PING" => Boolean(true),
+            _ => Error("ERR unexpected".into()),
+        });
+        let config = ConnectionConfig {
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            database: 1,
+            protocol: Some(Protocol::Resp3),
+            client_name: Some("app".into()),
+            ..Default::default()
+        };
+        let mut connection = Connection::connect_tcp(addr, &config)?;
+        let info = connection.server_info().unwrap();
+        assert_eq!(info.proto, Protocol::Resp3);
+        assert_eq!(info.version, "7.2.0");
+        assert_eq!(connection.command(vec!["PING".into()])?, Boolean(true));
+        Ok(())
+    }
+
+    #[test]
+    fn hello_noproto() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [hello, proto] if hello == b"HELLO" && proto == b"3" => {
+                Error("NOPROTO unsupported protocol version".into())
+            }
+            [hello, proto] if hello == b"HELLO" && proto == b"2" => hello_reply(Protocol::Resp2),
+            _ => Boolean(true),
+        });
+        let config = ConnectionConfig {
+            protocol: Some(Protocol::Resp3),
+            ..Default::default()
+        };
+        let mut connection = Connection::connect_tcp(addr, &config)?;
+        assert_eq!(connection.server_info().unwrap().proto, Protocol::Resp2);
+
+        // The parser now rejects RESP3 types
+        match connection.command(vec!["PING".into()]) {
+            Err(ClientError::ProtocolError(error)) => {
+                assert!(matches!(error.kind(), ParserError::TypeTokenError('#')))
+            }
+            other => panic!("expected a protocol error, got {:?}", other),
+        }
+        assert!(matches!(
+            connection.command(vec!["PING".into()]),
+            Err(ClientError::ClosedError)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn hello_renegotiate() -> ClientResult<()> {
+        let addr = serve(|command| match command.as_slice() {
+            [hello, proto] if hello == b"HELLO" && proto == b"2" => hello_reply(Protocol::Resp2),
+            [hello, proto] if hello == b"HELLO" && proto == b"3" => hello_reply(Protocol::Resp3),
+            _ => Boolean(true),
+        });
+        let config = ConnectionConfig {
+            protocol: Some(Protocol::Resp2),
+            ..Default::default()
+        };
+        let mut connection = Connection::connect_tcp(addr, &config)?;
+        assert_eq!(connection.server_info().unwrap().proto, Protocol::Resp2);
+
+        // The RESP3 map replying to HELLO 3 is read on a RESP2 connection
+        let info = connection.hello(Protocol::Resp3, &ConnectionConfig::default())?;
+        assert_eq!(info.proto, Protocol::Resp3);
+        assert_eq!(connection.command(vec!["PING".into()])?, Boolean(true));
+        Ok(())
+    }
+
+    #[test]
+    fn auth_failure() {
+        let addr = serve(|_| Error("WRONGPASS invalid username-password pair".into()));
+        let config = ConnectionConfig {
+            password: Some("wrong".into()),
+            ..Default::default()
+        };
+        match Connection::connect_tcp(addr, &config) {
+            Err(ClientError::ReplyError(error)) => assert_eq!(error.code.as_str(), "WRONGPASS"),
+            Err(other) => panic!("expected a reply error, got {:?}", other),
+            Ok(_) => panic!("expected a reply error"),
+        }
+    }
+
+    #[test]
+    fn read_timeout() -> ClientResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let config = ConnectionConfig {
+            read_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut connection = Connection::connect_tcp(listener.local_addr()?, &config)?;
+        match connection.command(vec!["PING".into()]) {
+            Err(ClientError::IoError(error)) => assert!(matches!(
+                error.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        // A late reply could be taken for the next one, so the connection
+        // is not used again
+        assert!(matches!(
+            connection.command(vec!["PING".into()]),
+            Err(ClientError::ClosedError)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn skip_pushes() -> ClientResult<()> {
+        let addr = serve_raw(|_| b">2\r\n+message\r\n+hi\r\n+PONG\r\n".to_vec());
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        assert_eq!(
+            connection.command(vec!["PING".into()])?,
+            SimpleString("PONG".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn try_receive() -> ClientResult<()> {
+        let addr = serve_raw(|_| b"+PONG\r\n>2\r\n+message\r\n+hi\r\n".to_vec());
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        assert_eq!(connection.try_receive()?, None);
+        assert_eq!(
+            connection.command(vec!["PING".into()])?,
+            SimpleString("PONG".into())
+        );
+        assert!(connection.try_receive()?.unwrap().is_push());
+        assert_eq!(connection.try_receive()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn closed() -> ClientResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        thread::spawn(move || drop(listener.accept()));
+        let mut connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        assert!(matches!(
+            connection.command(vec!["PING".into()]),
+            Err(ClientError::ClosedError) | Err(ClientError::IoError(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() -> ClientResult<()> {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("kresp-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buffer = [0; 64];
+            let _ = stream.read(&mut buffer).unwrap();
+            stream.write_all(b"+PONG\r\n").unwrap();
+        });
+
+        let mut connection = Connection::connect_unix(&path, &ConnectionConfig::default())?;
+        assert_eq!(
+            connection.command(vec!["PING".into()])?,
+            SimpleString("PONG".into())
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
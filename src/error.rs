@@ -0,0 +1,371 @@
+use std::fmt;
+use std::io;
+use std::str::Utf8Error;
+use thiserror::Error;
+
+use super::RespType;
+
+/// Result type used throughout this crate
+pub type Result<T> = std::result::Result<T, RespError>;
+
+/// Result type used by the clients
+pub type ClientResult<T> = std::result::Result<T, ClientError>;
+
+/// Error enumeration describing the kinds of errors that can occur
+#[derive(Error, Debug)]
+pub enum ParserError {
+    /// Error occured when reading a simple string that should end in \r\n
+    #[error("Invalid RESP line read: {0}")]
+    ReadlineError(String),
+
+    /// Error when an invalid size is read
+    #[error("Invalid RESP size: {0}")]
+    ReadsizeError(i64),
+
+    /// An internal state machine error, this should not happen, please report
+    /// if it does!
+    #[error("State error: {0}")]
+    StateError(String),
+
+    /// Next byte read was not a type token
+    #[error("Invalid RESP type token: {0:#?}")]
+    TypeTokenError(char),
+
+    /// Size limit hit
+    #[error("RESP size exceeded")]
+    SizeExceededError,
+
+    /// Nesting depth limit hit
+    #[error("RESP depth exceeded")]
+    DepthExceededError,
+
+    /// An integer or size could not be parsed
+    #[error("Invalid RESP integer: {0:?}")]
+    IntegerError(String),
+
+    /// A double could not be parsed
+    #[error("Invalid RESP double: {0:?}")]
+    DoubleError(String),
+
+    /// Data that must be UTF-8 was not
+    #[error("Invalid UTF-8: {0}")]
+    Utf8Error(#[from] Utf8Error),
+
+    /// A value was read that is not valid for its type
+    #[error("Invalid RESP value: {0}")]
+    ValueError(String),
+
+    /// Reading from or writing to the underlying stream failed
+    #[error("I/O error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+impl ParserError {
+    /// Attaches the byte offset where the error occurred
+    pub(crate) fn at(self, offset: usize) -> RespError {
+        RespError {
+            kind: self,
+            offset: Some(offset),
+        }
+    }
+}
+
+/// The error type of this crate, the [`ParserError`] kind can be matched on
+/// with [`RespError::kind`]
+#[derive(Debug)]
+pub struct RespError {
+    kind: ParserError,
+    offset: Option<usize>,
+}
+
+impl RespError {
+    /// The kind of error that occurred
+    pub fn kind(&self) -> &ParserError {
+        &self.kind
+    }
+
+    /// Consumes the error, returning its kind
+    pub fn into_kind(self) -> ParserError {
+        self.kind
+    }
+
+    /// Byte offset into the parsed stream where the error occurred, this is
+    /// `None` for errors that did not come from parsing
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Moves the offset along by the number of bytes already consumed
+    pub(crate) fn shifted(mut self, consumed: usize) -> Self {
+        self.offset = self.offset.map(|offset| offset + consumed);
+        self
+    }
+}
+
+impl From<ParserError> for RespError {
+    fn from(kind: ParserError) -> Self {
+        RespError { kind, offset: None }
+    }
+}
+
+impl From<Utf8Error> for RespError {
+    fn from(error: Utf8Error) -> Self {
+        ParserError::Utf8Error(error).into()
+    }
+}
+
+impl From<io::Error> for RespError {
+    fn from(error: io::Error) -> Self {
+        ParserError::IoError(error).into()
+    }
+}
+
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{} at byte {}", self.kind, offset),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for RespError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.kind)
+    }
+}
+
+/// Error enumeration for the clients, covering the connection itself as well
+/// as error replies sent by the server
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// The server sent data that could not be parsed
+    #[error(transparent)]
+    ProtocolError(#[from] RespError),
+
+    /// Reading from or writing to the connection failed, this includes timeouts
+    #[error("I/O error: {0}")]
+    IoError(#[from] io::Error),
+
+    /// The server replied with an error
+    #[error("Server error: {0}")]
+    ReplyError(ServerError),
+
+    /// The server replied with something other than what the command expects
+    #[error("Unexpected reply: {0:?}")]
+    UnexpectedReplyError(RespType),
+
+    /// The server closed the connection
+    #[error("Connection closed")]
+    ClosedError,
+
+    /// A different number of replies than expected was given
+    #[error("Expected {expected} replies, got {actual}")]
+    ReplyCountError { expected: usize, actual: usize },
+
+    /// The keys of a command hash to different cluster slots
+    #[error("Keys in request don't hash to the same slot")]
+    CrossSlotError,
+
+    /// A cluster command was still redirected after the configured limit
+    #[error("Too many cluster redirects")]
+    TooManyRedirectsError,
+
+    /// No sentinel knows the master with this name
+    #[error("Master not found: {0}")]
+    MasterNotFoundError(String),
+
+    /// The server only speaks RESP2, where tracking invalidations need a
+    /// second connection, see
+    /// [`CachingConnection::with_redirect`](crate::CachingConnection::with_redirect)
+    #[error("Server does not support RESP3")]
+    Resp3UnsupportedError,
+}
+
+/// An error reply from the server, split into its leading code and the rest
+/// of the message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerError {
+    /// The error code, the first word of the reply
+    pub code: ErrorCode,
+    /// Everything after the code
+    pub message: String,
+}
+
+/// Error codes with a known meaning, redirections carry their target
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The slot is served by another node, `MOVED <slot> <addr>`
+    Moved { slot: u16, addr: String },
+    /// The slot is being migrated, retry once on another node after
+    /// `ASKING`, `ASK <slot> <addr>`
+    Ask { slot: u16, addr: String },
+    /// The keys are being migrated, `TRYAGAIN`
+    TryAgain,
+    /// The cluster can not serve requests, `CLUSTERDOWN`
+    ClusterDown,
+    /// The key holds a value of another type, `WRONGTYPE`
+    WrongType,
+    /// No script matches the SHA1 digest, `NOSCRIPT`
+    NoScript,
+    /// A script or function is running, `BUSY`
+    Busy,
+    /// The dataset is still being loaded, `LOADING`
+    Loading,
+    /// The protocol version asked for with `HELLO` is not supported,
+    /// `NOPROTO`
+    NoProto,
+    /// Any other code, such as `ERR`
+    Other(String),
+}
+
+impl ServerError {
+    /// Parses an error reply such as `MOVED 3999 127.0.0.1:6381`. A redirect
+    /// that can not be parsed is kept as [`ErrorCode::Other`].
+    pub fn parse(error: &str) -> Self {
+        let (code, message) = error.split_once(' ').unwrap_or((error, ""));
+        let code = match code {
+            "MOVED" | "ASK" => match parse_redirect(message) {
+                Some((slot, addr)) if code == "MOVED" => ErrorCode::Moved { slot, addr },
+                Some((slot, addr)) => ErrorCode::Ask { slot, addr },
+                None => ErrorCode::Other(code.to_string()),
+            },
+            "TRYAGAIN" => ErrorCode::TryAgain,
+            "CLUSTERDOWN" => ErrorCode::ClusterDown,
+            "WRONGTYPE" => ErrorCode::WrongType,
+            "NOSCRIPT" => ErrorCode::NoScript,
+            "BUSY" => ErrorCode::Busy,
+            "LOADING" => ErrorCode::Loading,
+            "NOPROTO" => ErrorCode::NoProto,
+            code => ErrorCode::Other(code.to_string()),
+        };
+        ServerError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn parse_redirect(message: &str) -> Option<(u16, String)> {
+    let (slot, addr) = message.split_once(' ')?;
+    Some((slot.parse().ok()?, addr.to_string()))
+}
+
+impl ErrorCode {
+    /// The code as sent by the server
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorCode::Moved { .. } => "MOVED",
+            ErrorCode::Ask { .. } => "ASK",
+            ErrorCode::TryAgain => "TRYAGAIN",
+            ErrorCode::ClusterDown => "CLUSTERDOWN",
+            ErrorCode::WrongType => "WRONGTYPE",
+            ErrorCode::NoScript => "NOSCRIPT",
+            ErrorCode::Busy => "BUSY",
+            ErrorCode::Loading => "LOADING",
+            ErrorCode::NoProto => "NOPROTO",
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message.is_empty() {
+            true => write!(f, "{}", self.code.as_str()),
+            false => write!(f, "{} {}", self.code.as_str(), self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let error = ParserError::TypeTokenError('x').at(12);
+        assert_eq!(error.to_string(), "Invalid RESP type token: 'x' at byte 12");
+
+        let error: RespError = ParserError::SizeExceededError.into();
+        assert_eq!(error.to_string(), "RESP size exceeded");
+        assert_eq!(error.offset(), None);
+    }
+
+    #[test]
+    fn shifted() {
+        let error = ParserError::DepthExceededError.at(3).shifted(10);
+        assert_eq!(error.offset(), Some(13));
+        assert!(matches!(error.kind(), ParserError::DepthExceededError));
+    }
+
+    mod server_error {
+        use super::*;
+
+        #[test]
+        fn redirects() {
+            let error = ServerError::parse("MOVED 3999 127.0.0.1:6381");
+            assert_eq!(
+                error.code,
+                ErrorCode::Moved {
+                    slot: 3999,
+                    addr: "127.0.0.1:6381".into()
+                }
+            );
+            assert_eq!(error.message, "3999 127.0.0.1:6381");
+
+            let error = ServerError::parse("ASK 3999 127.0.0.1:6381");
+            assert_eq!(
+                error.code,
+                ErrorCode::Ask {
+                    slot: 3999,
+                    addr: "127.0.0.1:6381".into()
+                }
+            );
+        }
+
+        #[test]
+        fn malformed_redirect() {
+            let error = ServerError::parse("MOVED nope");
+            assert_eq!(error.code, ErrorCode::Other("MOVED".into()));
+            assert_eq!(error.message, "nope");
+        }
+
+        #[test]
+        fn codes() {
+            let cases = [
+                (
+                    "TRYAGAIN Multiple keys request during rehashing of slot",
+                    ErrorCode::TryAgain,
+                ),
+                ("CLUSTERDOWN The cluster is down", ErrorCode::ClusterDown),
+                (
+                    "WRONGTYPE Operation against a key holding the wrong kind of value",
+                    ErrorCode::WrongType,
+                ),
+                (
+                    "NOSCRIPT No matching script. Please use EVAL.",
+                    ErrorCode::NoScript,
+                ),
+                ("BUSY Redis is busy running a script.", ErrorCode::Busy),
+                (
+                    "LOADING Redis is loading the dataset in memory",
+                    ErrorCode::Loading,
+                ),
+                ("NOPROTO unsupported protocol version", ErrorCode::NoProto),
+                ("ERR unknown command", ErrorCode::Other("ERR".into())),
+            ];
+            for (reply, code) in cases {
+                assert_eq!(ServerError::parse(reply).code, code);
+            }
+        }
+
+        #[test]
+        fn display() {
+            for reply in ["ERR unknown command", "MOVED 1 a:1", "CLUSTERDOWN"] {
+                assert_eq!(ServerError::parse(reply).to_string(), reply);
+            }
+            assert_eq!(ServerError::parse("CLUSTERDOWN").message, "");
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::error::{ClientError, ClientResult};
+use super::ConnectionConfig;
+use super::Protocol;
+use super::RespType;
+
+/// Details about the server, as returned by `HELLO`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    /// Name of the server software, such as `redis`
+    pub server: String,
+    /// Version of the server software
+    pub version: String,
+    /// The protocol version in use on the connection
+    pub proto: Protocol,
+    /// ID of the connection, as shown by `CLIENT ID`
+    pub id: i64,
+    /// How the server runs: `standalone`, `sentinel` or `cluster`
+    pub mode: String,
+    /// Replication role of the server, such as `master` or `replica`
+    pub role: String,
+    /// Loaded modules, each described by a map
+    pub modules: Vec<RespType>,
+}
+
+impl TryFrom<RespType> for ServerInfo {
+    type Error = ClientError;
+
+    /// Decodes the `HELLO` reply, a map on RESP3 or an array of key/value
+    /// pairs on RESP2
+    fn try_from(resp: RespType) -> ClientResult<Self> {
+        match decode(&resp) {
+            Some(info) => Ok(info),
+            None => Err(ClientError::UnexpectedReplyError(resp)),
+        }
+    }
+}
+
+fn decode(resp: &RespType) -> Option<ServerInfo> {
+    let pairs: Vec<(&RespType, &RespType)> = match resp {
+        RespType::Map(pairs) => pairs.iter().map(|(key, value)| (key, value)).collect(),
+        RespType::Array(elements) if elements.len() % 2 == 0 => elements
+            .chunks_exact(2)
+            .map(|pair| (&pair[0], &pair[1]))
+            .collect(),
+        _ => return None,
+    };
+    let field = |name: &str| {
+        pairs
+            .iter()
+            .find(|(key, _)| key.as_text() == Some(name))
+            .map(|(_, value)| *value)
+    };
+    let string = |name: &str| field(name).and_then(RespType::as_text).map(str::to_string);
+    let integer = |name: &str| match field(name)? {
+        RespType::Integer(integer) => Some(*integer),
+        _ => None,
+    };
+
+    Some(ServerInfo {
+        server: string("server")?,
+        version: string("version")?,
+        proto: match integer("proto")? {
+            2 => Protocol::Resp2,
+            3 => Protocol::Resp3,
+            _ => return None,
+        },
+        id: integer("id")?,
+        mode: string("mode")?,
+        role: string("role")?,
+        modules: match field("modules")? {
+            RespType::Array(modules) | RespType::Set(modules) => modules.clone(),
+            _ => return None,
+        },
+    })
+}
+
+/// Builds `HELLO <protover> [AUTH user pass] [SETNAME name]` from the config,
+/// authenticating as the `default` user when no username is set
+pub(crate) fn hello_command(protocol: Protocol, config: &ConnectionConfig) -> Vec<Vec<u8>> {
+    let mut command = vec!["HELLO".into(), (protocol as u8).to_string().into()];
+    if let Some(password) = &config.password {
+        let username = config.username.as_deref().unwrap_or("default");
+        command.extend([
+            "AUTH".into(),
+            username.into(),
+            password.clone().into_bytes(),
+        ]);
+    }
+    if let Some(name) = &config.client_name {
+        command.extend(["SETNAME".into(), name.clone().into_bytes()]);
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::hello_reply;
+    use RespType::*;
+
+    #[test]
+    fn decode_map() -> ClientResult<()> {
+        let info = ServerInfo::try_from(hello_reply(Protocol::Resp3))?;
+        assert_eq!(info.server, "redis");
+        assert_eq!(info.version, "7.2.0");
+        assert_eq!(info.proto, Protocol::Resp3);
+        assert_eq!(info.id, 7);
+        assert_eq!(info.mode, "standalone");
+        assert_eq!(info.role, "master");
+        assert!(info.modules.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_array() -> ClientResult<()> {
+        let info = ServerInfo::try_from(hello_reply(Protocol::Resp2))?;
+        assert_eq!(info.proto, Protocol::Resp2);
+        assert_eq!(info.id, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let reply = Map(vec![(
+            BulkString("server".into()),
+            BulkString("redis".into()),
+        )]);
+        assert!(matches!(
+            ServerInfo::try_from(reply),
+            Err(ClientError::UnexpectedReplyError(_))
+        ));
+        assert!(ServerInfo::try_from(SimpleString("OK".into())).is_err());
+    }
+
+    #[test]
+    fn command() {
+        let config = ConnectionConfig {
+            password: Some("secret".into()),
+            client_name: Some("app".into()),
+            ..Default::default()
+        };
+        let command: Vec<Vec<u8>> = ["HELLO", "3", "AUTH", "default", "secret", "SETNAME", "app"]
+            .iter()
+            .map(|arg| arg.as_bytes().to_vec())
+            .collect();
+        assert_eq!(hello_command(Protocol::Resp3, &config), command);
+        assert_eq!(
+            hello_command(Protocol::Resp2, &ConnectionConfig::default()),
+            vec![b"HELLO".to_vec(), b"2".to_vec()]
+        );
+    }
+}
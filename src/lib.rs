@@ -8,11 +8,51 @@
 //! preserve the buffers and parsing state to minimize re-parsing of incomplete
 //! data that could stream over a network connection.
 
+#[cfg(feature = "tokio")]
+mod async_connection;
 mod buffer;
+#[cfg(feature = "bytes")]
+mod bytes_parser;
+mod cache;
+pub mod cluster;
+mod cluster_connection;
+#[cfg(feature = "tokio")]
+mod codec;
 mod config;
+mod connection;
+mod error;
+mod hello;
 mod parser;
+mod pipeline;
+mod pubsub;
 mod resp;
+mod resp_ref;
+mod sentinel;
+pub mod server;
+mod streamed;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod transaction;
 
-pub use config::RespConfig;
-pub use parser::{ParserError, RespParser};
+#[cfg(feature = "tokio")]
+pub use async_connection::AsyncConnection;
+#[cfg(feature = "bytes")]
+pub use bytes_parser::{BytesFrame, BytesParser};
+pub use cache::{CachingConnection, TrackingMode};
+pub use cluster_connection::{ClusterConfig, ClusterConnection, SlotRange};
+#[cfg(feature = "tokio")]
+pub use codec::RespCodec;
+pub use config::{Protocol, RespConfig};
+pub use connection::{Connection, ConnectionConfig};
+pub use error::{
+    ClientError, ClientResult, ErrorCode, ParserError, RespError, Result, ServerError,
+};
+pub use hello::ServerInfo;
+pub use parser::RespParser;
+pub use pipeline::{Pipeline, PipelineReplies};
+pub use pubsub::{Message, PubSubEvent, Subscriber, SubscriptionKind};
 pub use resp::RespType;
+pub use resp_ref::{parse_ref, RespRef};
+pub use sentinel::{Sentinel, SentinelConfig};
+pub use streamed::StreamedType;
+pub use transaction::{Transaction, TransactionOutcome};
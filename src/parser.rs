@@ -1,38 +1,17 @@
-use anyhow::Result;
-use thiserror::Error;
-
 use super::buffer::*;
+use super::error::{ParserError, RespError, Result};
+use super::resp::is_big_number;
 use super::RespConfig;
+use super::RespRef;
 use super::RespType;
 
-/// Error enumeration used when a parsing error occurs
-#[derive(Error, Debug)]
-pub enum ParserError {
-    /// Error occured when reading a simple string that should end in \r\n
-    #[error("Invalid RESP line read: {0}")]
-    ReadlineError(String),
-
-    /// Error when an invalid size is read
-    #[error("Invalid RESP size: {0}")]
-    ReadsizeError(i64),
-
-    /// An internal state machine error, this should not happen, please report
-    /// if it does!
-    #[error("State error: {0}")]
-    StateError(String),
-
-    /// Next byte read was not a type token
-    #[error("Invalid RESP type token: {0:#?}")]
-    TypeTokenError(char),
-
-    /// Size limit hit
-    #[error("RESP size exceeded")]
-    SizeExceededError,
-}
+/// Longest inline command accepted, as in redis-server
+const INLINE_MAX_SIZE: usize = 64 * 1024;
 
 /// The parser itself, use [`RespParser::read`] to provide it buffers to parse
 pub struct RespParser {
     buffer: Vec<u8>,
+    consumed: usize,
     state: Option<Box<State>>,
     /// Configuration structure for memory limits
     pub config: RespConfig,
@@ -42,6 +21,7 @@ pub struct RespParser {
 enum State {
     GetType {
         cursor: usize,
+        depth: usize,
     },
     Simple {
         cursor: usize,
@@ -52,13 +32,30 @@ enum State {
         cursor: usize,
         start: usize,
         size: Option<usize>,
+        bulk_type: BulkType,
     },
-    Array {
+    StreamedString {
         cursor: usize,
         start: usize,
         size: Option<usize>,
+        data: Vec<u8>,
+    },
+    Aggregate {
+        cursor: usize,
+        start: usize,
+        size: Option<AggregateSize>,
         elements: Option<Vec<RespType>>,
         substate: Option<Box<State>>,
+        aggregate_type: AggregateType,
+        depth: usize,
+    },
+    Attributed {
+        attributes: Vec<(RespType, RespType)>,
+        substate: Box<State>,
+    },
+    Inline {
+        cursor: usize,
+        start: usize,
     },
 }
 
@@ -67,8 +64,8 @@ impl State {
         Box::new(self)
     }
 
-    fn get_type(cursor: usize) -> Box<State> {
-        Box::new(State::GetType { cursor })
+    fn get_type(cursor: usize, depth: usize) -> Box<State> {
+        Box::new(State::GetType { cursor, depth })
     }
 
     fn get_simple(cursor: usize, simple_type: SimpleType) -> Box<State> {
@@ -79,21 +76,24 @@ impl State {
         })
     }
 
-    fn get_bulk_string(cursor: usize) -> Box<State> {
+    fn get_bulk_string(cursor: usize, bulk_type: BulkType) -> Box<State> {
         Box::new(State::BulkString {
             cursor,
             start: cursor,
             size: None,
+            bulk_type,
         })
     }
 
-    fn get_array(cursor: usize) -> Box<State> {
-        Box::new(State::Array {
+    fn get_aggregate(cursor: usize, aggregate_type: AggregateType, depth: usize) -> Box<State> {
+        Box::new(State::Aggregate {
             cursor,
             start: cursor,
             size: None,
             elements: None,
             substate: None,
+            aggregate_type,
+            depth,
         })
     }
 }
@@ -105,10 +105,145 @@ enum StateResult {
 }
 
 #[derive(Debug)]
-enum SimpleType {
+pub(crate) enum SimpleType {
     String,
     Error,
     Integer,
+    Null,
+    Boolean,
+    Double,
+    BigNumber,
+}
+
+impl SimpleType {
+    pub(crate) fn build<'a>(&self, line: &'a str, start: usize) -> Result<RespRef<'a>> {
+        let result = match self {
+            SimpleType::String => RespRef::SimpleString(line),
+            SimpleType::Error => RespRef::Error(line),
+            SimpleType::Integer => RespRef::Integer(
+                line.parse()
+                    .map_err(|_| ParserError::IntegerError(line.to_string()).at(start))?,
+            ),
+            SimpleType::Null => match line.is_empty() {
+                true => RespRef::Nil,
+                false => {
+                    let error = format!("null type with data {:?}", line);
+                    return Err(ParserError::ValueError(error).at(start));
+                }
+            },
+            SimpleType::Boolean => match line {
+                "t" => RespRef::Boolean(true),
+                "f" => RespRef::Boolean(false),
+                other => {
+                    let error = format!("boolean type with data {:?}", other);
+                    return Err(ParserError::ValueError(error).at(start));
+                }
+            },
+            SimpleType::Double => RespRef::Double(
+                line.parse()
+                    .map_err(|_| ParserError::DoubleError(line.to_string()).at(start))?,
+            ),
+            SimpleType::BigNumber => match is_big_number(line) {
+                true => RespRef::BigNumber(line),
+                false => {
+                    let error = format!("big number type with data {:?}", line);
+                    return Err(ParserError::ValueError(error).at(start));
+                }
+            },
+        };
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BulkType {
+    String,
+    Verbatim,
+    Error,
+}
+
+impl BulkType {
+    fn build(&self, data: Vec<u8>, cursor: usize) -> Result<RespType> {
+        let result = match self {
+            BulkType::String => RespType::BulkString(data),
+            BulkType::Error => RespType::BlobError(data),
+            BulkType::Verbatim => {
+                if data.len() < 4 || data[3] != b':' {
+                    let error = "verbatim string without a format prefix".to_string();
+                    return Err(ParserError::ValueError(error).at(cursor));
+                }
+                let format = std::str::from_utf8(&data[..3])
+                    .map_err(|error| ParserError::Utf8Error(error).at(cursor))?
+                    .to_string();
+                let text = data[4..].to_vec();
+                RespType::VerbatimString { format, text }
+            }
+        };
+        Ok(result)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AggregateType {
+    Array,
+    Map,
+    Set,
+    Push,
+    Attribute,
+}
+
+impl AggregateType {
+    /// Number of elements read for each unit of the declared size
+    fn width(&self) -> usize {
+        match self {
+            AggregateType::Map | AggregateType::Attribute => 2,
+            _ => 1,
+        }
+    }
+
+    fn build(&self, elements: Vec<RespType>) -> RespType {
+        match self {
+            AggregateType::Array => RespType::Array(elements),
+            AggregateType::Set => RespType::Set(elements),
+            AggregateType::Push => RespType::Push(elements),
+            AggregateType::Map | AggregateType::Attribute => RespType::Map(into_pairs(elements)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AggregateSize {
+    Sized(usize),
+    Streamed,
+}
+
+fn is_type_token(token: u8) -> bool {
+    matches!(
+        token,
+        b'+' | b'-'
+            | b':'
+            | b'_'
+            | b'#'
+            | b','
+            | b'('
+            | b'$'
+            | b'='
+            | b'!'
+            | b'*'
+            | b'%'
+            | b'~'
+            | b'>'
+            | b'|'
+    )
+}
+
+pub(crate) fn into_pairs<T>(elements: Vec<T>) -> Vec<(T, T)> {
+    let mut pairs = Vec::with_capacity(elements.len() / 2);
+    let mut elements = elements.into_iter();
+    while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+        pairs.push((key, value));
+    }
+    pairs
 }
 
 impl Default for RespParser {
@@ -122,6 +257,7 @@ impl RespParser {
     pub fn new(config: RespConfig) -> Self {
         RespParser {
             buffer: Vec::new(),
+            consumed: 0,
             state: None,
             config,
         }
@@ -129,16 +265,24 @@ impl RespParser {
 
     /// Copy and parses the provided buffer, returns a list of [`RespType`] variant results
     pub fn read(&mut self, buffer: &[u8]) -> Result<Vec<RespType>> {
-        for byte in buffer {
-            self.buffer.push(*byte);
-        }
+        self.read_limited(buffer, usize::MAX)
+    }
+
+    /// Like [`RespParser::read`], but returns at most `limit` results. Any
+    /// further data stays buffered and is parsed by the next read, which can
+    /// be given an empty buffer.
+    pub fn read_limited(&mut self, buffer: &[u8], limit: usize) -> Result<Vec<RespType>> {
+        self.buffer.extend_from_slice(buffer);
 
         if self.buffer.len() > self.config.max_buffer_size {
-            self.buffer.clear();
-            return Err(ParserError::SizeExceededError.into());
+            let error = ParserError::SizeExceededError.at(self.config.max_buffer_size);
+            return Err(self.fail(error));
         }
 
         let mut items = Vec::new();
+        if limit == 0 {
+            return Ok(items);
+        }
         if let Some(state) = self.state.take() {
             match self.process_state(state) {
                 Ok(result) => match result {
@@ -147,67 +291,119 @@ impl RespParser {
                         return Ok(items);
                     }
                     StateResult::Done(item, end) => {
-                        self.buffer.drain(..end);
+                        self.consume(end);
                         items.push(item)
                     }
                 },
-                Err(error) => {
-                    self.buffer.clear();
-                    return Err(error);
-                }
+                Err(error) => return Err(self.fail(error)),
             }
         }
 
-        loop {
+        while items.len() < limit {
             match self.get_next() {
                 Ok(result) => match result {
                     Some(item) => items.push(item),
                     None => return Ok(items),
                 },
-                Err(error) => {
-                    self.buffer.clear();
-                    return Err(error);
-                }
+                Err(error) => return Err(self.fail(error)),
             }
         }
+        Ok(items)
+    }
+
+    /// Like [`RespParser::read`], but separates RESP3 push frames from ordinary
+    /// replies, returned as `(replies, pushes)` with each in the order received
+    pub fn read_split(&mut self, buffer: &[u8]) -> Result<(Vec<RespType>, Vec<RespType>)> {
+        let items = self.read(buffer)?;
+        Ok(items.into_iter().partition(|item| !item.is_push()))
+    }
+
+    /// Checks if no partially read data is being held, such as the start of
+    /// a reply that has not fully arrived yet
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
     }
 
     fn get_next(&mut self) -> Result<Option<RespType>> {
-        match self.get_type(State::get_type(0))? {
+        match self.get_type(State::get_type(0, 0))? {
             StateResult::Incomplete(state) => {
                 self.state = Some(state.boxed());
                 Ok(None)
             }
             StateResult::Done(item, end) => {
-                self.buffer.drain(..end);
+                self.consume(end);
                 Ok(Some(item))
             }
         }
     }
 
+    fn consume(&mut self, end: usize) {
+        self.buffer.drain(..end);
+        self.consumed += end;
+    }
+
+    /// Clears the internal buffers after an error, so the parser can be reused,
+    /// and makes the error offset relative to the start of the stream
+    fn fail(&mut self, error: RespError) -> RespError {
+        let error = error.shifted(self.consumed);
+        self.consumed += self.buffer.len();
+        self.buffer.clear();
+        error
+    }
+
     fn process_state(&self, state: Box<State>) -> Result<StateResult> {
         match *state {
             State::GetType { .. } => self.get_type(state),
             State::Simple { .. } => self.get_simple(state),
             State::BulkString { .. } => self.get_bulk_string(state),
-            State::Array { .. } => self.get_array(state),
+            State::StreamedString { .. } => self.get_streamed_string(state),
+            State::Aggregate { .. } => self.get_aggregate(state),
+            State::Attributed { .. } => self.get_attributed(state),
+            State::Inline { .. } => self.get_inline(state),
         }
     }
 
     fn get_type(&self, state: Box<State>) -> Result<StateResult> {
-        if let State::GetType { cursor } = *state {
+        if let State::GetType { cursor, depth } = *state {
             if self.buffer.len() <= cursor {
-                return Ok(StateResult::Incomplete(State::get_type(cursor)));
+                return Ok(StateResult::Incomplete(State::get_type(cursor, depth)));
             }
 
             let next_cursor = cursor + 1;
+            let aggregate = |aggregate_type| {
+                if depth >= self.config.max_depth {
+                    return Err(ParserError::DepthExceededError.at(cursor));
+                }
+                Ok(State::get_aggregate(next_cursor, aggregate_type, depth + 1))
+            };
+            let token = self.buffer[cursor];
+            if depth == 0 && self.config.inline_commands && !is_type_token(token) {
+                let state = State::Inline {
+                    cursor,
+                    start: cursor,
+                };
+                return self.get_inline(state.boxed());
+            }
+            if !self.config.protocol.allows(token) {
+                return Err(ParserError::TypeTokenError(token as char).at(cursor));
+            }
             let state = match &self.buffer[cursor] {
                 b'+' => State::get_simple(next_cursor, SimpleType::String),
                 b'-' => State::get_simple(next_cursor, SimpleType::Error),
                 b':' => State::get_simple(next_cursor, SimpleType::Integer),
-                b'$' => State::get_bulk_string(next_cursor),
-                b'*' => State::get_array(next_cursor),
-                other => return Err(ParserError::TypeTokenError(*other as char).into()),
+                b'_' => State::get_simple(next_cursor, SimpleType::Null),
+                b'#' => State::get_simple(next_cursor, SimpleType::Boolean),
+                b',' => State::get_simple(next_cursor, SimpleType::Double),
+                b'(' => State::get_simple(next_cursor, SimpleType::BigNumber),
+                b'$' => State::get_bulk_string(next_cursor, BulkType::String),
+                b'=' => State::get_bulk_string(next_cursor, BulkType::Verbatim),
+                b'!' => State::get_bulk_string(next_cursor, BulkType::Error),
+                b'*' => aggregate(AggregateType::Array)?,
+                b'%' => aggregate(AggregateType::Map)?,
+                b'~' => aggregate(AggregateType::Set)?,
+                b'>' => aggregate(AggregateType::Push)?,
+                b'|' => aggregate(AggregateType::Attribute)?,
+                other => return Err(ParserError::TypeTokenError(*other as char).at(cursor)),
             };
 
             if self.buffer.len() > cursor + 1 {
@@ -224,6 +420,42 @@ impl RespParser {
         }
     }
 
+    fn get_inline(&self, state: Box<State>) -> Result<StateResult> {
+        if let State::Inline { cursor, start } = *state {
+            let limit = self.config.max_resp_size.min(INLINE_MAX_SIZE);
+            let newline = self.buffer[cursor..].iter().position(|byte| *byte == b'\n');
+            let end = match newline {
+                Some(newline) => cursor + newline,
+                None => {
+                    if self.buffer.len() - start > limit {
+                        return Err(ParserError::SizeExceededError.at(start));
+                    }
+                    let cursor = self.buffer.len();
+                    let state = State::Inline { cursor, start };
+                    return Ok(StateResult::Incomplete(state.boxed()));
+                }
+            };
+
+            let line = &self.buffer[start..end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.len() > limit {
+                return Err(ParserError::SizeExceededError.at(start));
+            }
+            let args = split_inline(line).ok_or_else(|| {
+                let error = "unbalanced quotes in inline command".to_string();
+                ParserError::ValueError(error).at(start)
+            })?;
+            let args = args.into_iter().map(RespType::BulkString).collect();
+            Ok(StateResult::Done(RespType::Array(args), end + 1))
+        } else {
+            Err(ParserError::StateError(format!(
+                "get_inline received wrong state type: {:#?}",
+                state
+            ))
+            .into())
+        }
+    }
+
     fn get_simple(&self, state: Box<State>) -> Result<StateResult> {
         if let State::Simple {
             cursor,
@@ -234,13 +466,9 @@ impl RespParser {
             match readline(&self.buffer, cursor, start)? {
                 ReadlineResult::Line { line, cursor } => {
                     if line.len() > self.config.max_resp_size {
-                        return Err(ParserError::SizeExceededError.into());
+                        return Err(ParserError::SizeExceededError.at(start));
                     }
-                    let result = match simple_type {
-                        SimpleType::String => RespType::SimpleString(line),
-                        SimpleType::Error => RespType::Error(line),
-                        SimpleType::Integer => RespType::Integer(line.parse()?),
-                    };
+                    let result = simple_type.build(line, start)?.to_owned();
                     Ok(StateResult::Done(result, cursor))
                 }
                 ReadlineResult::None { cursor } => Ok(StateResult::Incomplete(
@@ -266,6 +494,7 @@ impl RespParser {
             cursor,
             start,
             size: string_length,
+            bulk_type,
         } = *state
         {
             let (cursor, size) = match string_length {
@@ -275,24 +504,43 @@ impl RespParser {
                             cursor,
                             start,
                             size: None,
+                            bulk_type,
                         };
                         return Ok(StateResult::Incomplete(state.boxed()));
                     }
-                    ReadsizeResult::Null(cursor) => {
-                        let result = RespType::Null;
-                        return Ok(StateResult::Done(result, cursor));
-                    }
+                    ReadsizeResult::Null(cursor) => match bulk_type {
+                        BulkType::String => {
+                            let result = RespType::Null;
+                            return Ok(StateResult::Done(result, cursor));
+                        }
+                        _ => return Err(ParserError::ReadsizeError(-1).at(start)),
+                    },
                     ReadsizeResult::Size { end, size } => (end, size),
+                    ReadsizeResult::Streamed(end) => match bulk_type {
+                        BulkType::String => {
+                            let state = State::StreamedString {
+                                cursor: end,
+                                start: end,
+                                size: None,
+                                data: Vec::new(),
+                            };
+                            return self.get_streamed_string(state.boxed());
+                        }
+                        _ => {
+                            let error = format!("{:?} can not be streamed", bulk_type);
+                            return Err(ParserError::ValueError(error).at(start));
+                        }
+                    },
                 },
                 Some(size) => (cursor, size),
             };
             if size > self.config.max_resp_size {
-                return Err(ParserError::SizeExceededError.into());
+                return Err(ParserError::SizeExceededError.at(start));
             }
 
             match readbuffer(&self.buffer, cursor, size) {
                 Some((vector, end)) => {
-                    let result = RespType::BulkString(vector);
+                    let result = bulk_type.build(vector, cursor)?;
                     Ok(StateResult::Done(result, end))
                 }
                 None => {
@@ -300,6 +548,7 @@ impl RespParser {
                         cursor,
                         start,
                         size: Some(size),
+                        bulk_type,
                     }
                     .boxed();
                     Ok(StateResult::Incomplete(state))
@@ -314,55 +563,113 @@ impl RespParser {
         }
     }
 
-    fn get_array(&self, state: Box<State>) -> Result<StateResult> {
-        if let State::Array {
+    fn get_aggregate(&self, state: Box<State>) -> Result<StateResult> {
+        if let State::Aggregate {
             cursor,
             start,
-            size: array_size,
+            size: aggregate_size,
             elements,
             mut substate,
+            aggregate_type,
+            depth,
         } = *state
         {
-            let (cursor, size) = match array_size {
+            let (cursor, size) = match aggregate_size {
                 Some(size) => (cursor, size),
                 None => match readsize(&self.buffer, cursor, start)? {
                     ReadsizeResult::None(cursor) => {
-                        let state = State::Array {
+                        let state = State::Aggregate {
                             cursor,
                             start,
                             size: None,
                             elements: None,
                             substate: None,
+                            aggregate_type,
+                            depth,
                         };
                         return Ok(StateResult::Incomplete(state.boxed()));
                     }
-                    ReadsizeResult::Null(cursor) => {
-                        let result = RespType::NullArray;
-                        return Ok(StateResult::Done(result, cursor));
-                    }
-                    ReadsizeResult::Size { end, size } => {
-                        if size == 0 {
-                            let result = RespType::Array(Vec::new());
-                            return Ok(StateResult::Done(result, end));
-                        } else {
-                            (end, size)
+                    ReadsizeResult::Null(cursor) => match aggregate_type {
+                        AggregateType::Array => {
+                            let result = RespType::NullArray;
+                            return Ok(StateResult::Done(result, cursor));
                         }
-                    }
+                        _ => return Err(ParserError::ReadsizeError(-1).at(start)),
+                    },
+                    ReadsizeResult::Size { end, size } => (end, AggregateSize::Sized(size)),
+                    ReadsizeResult::Streamed(end) => match aggregate_type {
+                        AggregateType::Array | AggregateType::Map | AggregateType::Set => {
+                            (end, AggregateSize::Streamed)
+                        }
+                        _ => {
+                            let error = format!("{:?} can not be streamed", aggregate_type);
+                            return Err(ParserError::ValueError(error).at(start));
+                        }
+                    },
                 },
             };
-            if size > self.config.max_resp_size {
-                return Err(ParserError::SizeExceededError.into());
+            if let AggregateSize::Sized(size) = size {
+                if size > self.config.max_resp_size / aggregate_type.width() {
+                    return Err(ParserError::SizeExceededError.at(start));
+                }
             }
 
-            let mut elements = match elements {
-                Some(elements) => elements,
-                None => Vec::new(),
+            let incomplete = |cursor, elements, substate| {
+                let state = State::Aggregate {
+                    cursor,
+                    start,
+                    size: Some(size),
+                    elements: Some(elements),
+                    substate,
+                    aggregate_type,
+                    depth,
+                };
+                Ok(StateResult::Incomplete(state.boxed()))
             };
+
+            let mut elements = elements.unwrap_or_default();
             let mut cursor = cursor;
-            while elements.len() < size {
+            loop {
+                match size {
+                    AggregateSize::Sized(size) => {
+                        if elements.len() >= size * aggregate_type.width() {
+                            break;
+                        }
+                    }
+                    AggregateSize::Streamed if substate.is_none() => {
+                        match self.buffer.get(cursor) {
+                            None => return incomplete(cursor, elements, None),
+                            Some(b'.') => match readline(&self.buffer, cursor + 1, cursor + 1)? {
+                                ReadlineResult::Line { line, cursor: end } => {
+                                    if !line.is_empty() {
+                                        let error = format!("end of stream with data {:?}", line);
+                                        return Err(ParserError::ValueError(error).at(cursor));
+                                    }
+                                    if elements.len() % aggregate_type.width() != 0 {
+                                        let error =
+                                            "streamed map ended without a value".to_string();
+                                        return Err(ParserError::ValueError(error).at(cursor));
+                                    }
+                                    cursor = end;
+                                    break;
+                                }
+                                ReadlineResult::None { .. } => {
+                                    return incomplete(cursor, elements, None)
+                                }
+                            },
+                            Some(_) => {
+                                if elements.len() >= self.config.max_resp_size {
+                                    return Err(ParserError::SizeExceededError.at(cursor));
+                                }
+                            }
+                        }
+                    }
+                    AggregateSize::Streamed => {}
+                }
+
                 let state = match substate {
                     Some(_) => substate.take().unwrap(),
-                    None => State::get_type(cursor),
+                    None => State::get_type(cursor, depth),
                 };
                 match self.process_state(state)? {
                     StateResult::Done(result, end) => {
@@ -370,22 +677,148 @@ impl RespParser {
                         elements.push(result);
                     }
                     StateResult::Incomplete(substate) => {
-                        let state = State::Array {
+                        return incomplete(cursor, elements, Some(substate))
+                    }
+                }
+            }
+            match aggregate_type {
+                AggregateType::Attribute => {
+                    let state = State::Attributed {
+                        attributes: into_pairs(elements),
+                        substate: State::get_type(cursor, depth),
+                    };
+                    self.get_attributed(state.boxed())
+                }
+                _ => {
+                    let result = aggregate_type.build(elements);
+                    Ok(StateResult::Done(result, cursor))
+                }
+            }
+        } else {
+            Err(ParserError::StateError(format!(
+                "get_aggregate received wrong state type: {:#?}",
+                state
+            ))
+            .into())
+        }
+    }
+
+    fn get_streamed_string(&self, state: Box<State>) -> Result<StateResult> {
+        if let State::StreamedString {
+            mut cursor,
+            mut start,
+            mut size,
+            mut data,
+        } = *state
+        {
+            loop {
+                let chunk_size = match size {
+                    Some(size) => size,
+                    None => {
+                        match self.buffer.get(start) {
+                            None => {
+                                let state = State::StreamedString {
+                                    cursor,
+                                    start,
+                                    size: None,
+                                    data,
+                                };
+                                return Ok(StateResult::Incomplete(state.boxed()));
+                            }
+                            Some(b';') => {}
+                            Some(other) => {
+                                let error =
+                                    format!("expected ';' chunk header, got {:?}", *other as char);
+                                return Err(ParserError::ValueError(error).at(start));
+                            }
+                        }
+                        match readsize(&self.buffer, cursor.max(start + 1), start + 1)? {
+                            ReadsizeResult::None(cursor) => {
+                                let state = State::StreamedString {
+                                    cursor,
+                                    start,
+                                    size: None,
+                                    data,
+                                };
+                                return Ok(StateResult::Incomplete(state.boxed()));
+                            }
+                            ReadsizeResult::Size { end, size: 0 } => {
+                                let result = RespType::BulkString(data);
+                                return Ok(StateResult::Done(result, end));
+                            }
+                            ReadsizeResult::Size { end, size } => {
+                                cursor = end;
+                                size
+                            }
+                            ReadsizeResult::Null(_) => {
+                                return Err(ParserError::ReadsizeError(-1).at(start))
+                            }
+                            ReadsizeResult::Streamed(_) => {
+                                let error = "chunk of unknown size".to_string();
+                                return Err(ParserError::ValueError(error).at(start));
+                            }
+                        }
+                    }
+                };
+                if data.len() + chunk_size > self.config.max_resp_size {
+                    return Err(ParserError::SizeExceededError.at(start));
+                }
+
+                match readbuffer(&self.buffer, cursor, chunk_size) {
+                    Some((chunk, end)) => {
+                        data.extend(chunk);
+                        cursor = end;
+                        start = end;
+                        size = None;
+                    }
+                    None => {
+                        let state = State::StreamedString {
                             cursor,
                             start,
-                            size: Some(size),
-                            elements: Some(elements),
-                            substate: Some(substate),
+                            size: Some(chunk_size),
+                            data,
                         };
                         return Ok(StateResult::Incomplete(state.boxed()));
                     }
                 }
             }
-            let result = RespType::Array(elements);
-            Ok(StateResult::Done(result, cursor))
         } else {
             Err(ParserError::StateError(format!(
-                "get_array received wrong state type: {:#?}",
+                "get_streamed_string received wrong state type: {:#?}",
+                state
+            ))
+            .into())
+        }
+    }
+
+    fn get_attributed(&self, state: Box<State>) -> Result<StateResult> {
+        if let State::Attributed {
+            attributes,
+            substate,
+        } = *state
+        {
+            match self.process_state(substate)? {
+                StateResult::Done(reply, end) => {
+                    let result = match self.config.discard_attributes {
+                        true => reply,
+                        false => RespType::Attribute {
+                            attributes,
+                            reply: Box::new(reply),
+                        },
+                    };
+                    Ok(StateResult::Done(result, end))
+                }
+                StateResult::Incomplete(substate) => {
+                    let state = State::Attributed {
+                        attributes,
+                        substate,
+                    };
+                    Ok(StateResult::Incomplete(state.boxed()))
+                }
+            }
+        } else {
+            Err(ParserError::StateError(format!(
+                "get_attributed received wrong state type: {:#?}",
                 state
             ))
             .into())
@@ -394,8 +827,10 @@ impl RespParser {
 }
 
 #[cfg(test)]
+#[allow(clippy::ptr_arg)]
 mod tests {
     use super::*;
+    use crate::Protocol;
     use RespType::*;
 
     fn test_parser_ok<'a, T>(buffer: T) -> Vec<RespType>
@@ -510,6 +945,102 @@ mod tests {
         }
     }
 
+    #[test]
+    fn read_limited() -> Result<()> {
+        let mut parser = RespParser::default();
+        assert_eq!(parser.read_limited(b":1\r\n:2\r\n:3", 1)?, vec![Integer(1)]);
+        assert_eq!(parser.read_limited(b"", 0)?, vec![]);
+        assert_eq!(
+            parser.read_limited(b"\r\n", 5)?,
+            vec![Integer(2), Integer(3)]
+        );
+        assert!(parser.is_empty());
+        Ok(())
+    }
+
+    mod inline {
+        use super::*;
+
+        fn inline_parser() -> RespParser {
+            RespParser::new(RespConfig {
+                inline_commands: true,
+                ..Default::default()
+            })
+        }
+
+        fn command(args: &[&str]) -> RespType {
+            Array(
+                args.iter()
+                    .map(|arg| BulkString(arg.as_bytes().to_vec()))
+                    .collect(),
+            )
+        }
+
+        #[test]
+        fn commands() -> Result<()> {
+            let mut parser = inline_parser();
+            let results = parser.read(b"PING\r\nSET a \"b c\"\n*1\r\n$4\r\nPING\r\n\r\n")?;
+            assert_eq!(
+                results,
+                vec![
+                    command(&["PING"]),
+                    command(&["SET", "a", "b c"]),
+                    command(&["PING"]),
+                    command(&[]),
+                ]
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn onebyte() -> Result<()> {
+            let mut parser = inline_parser();
+            let mut results = Vec::new();
+            for byte in b"GET 'key'\r\n" {
+                results.extend(parser.read(&[*byte])?);
+            }
+            assert_eq!(results, vec![command(&["GET", "key"])]);
+            Ok(())
+        }
+
+        #[test]
+        fn disabled() {
+            test_parser_err(b"PING\r\n");
+        }
+
+        #[test]
+        fn nested() {
+            // Inline commands are only read at the top level
+            let error = inline_parser().read(b"*1\r\nPING\r\n").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::TypeTokenError('P')));
+        }
+
+        #[test]
+        fn unbalanced_quotes() {
+            let error = inline_parser().read(b"+OK\r\nSET \"a\r\n").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::ValueError(_)));
+            assert_eq!(error.offset(), Some(5));
+        }
+
+        #[test]
+        fn size_exceeded() -> Result<()> {
+            let mut parser = RespParser::new(RespConfig {
+                inline_commands: true,
+                max_resp_size: 8,
+                ..Default::default()
+            });
+            let error = parser.read(b"GET 0123456789").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::SizeExceededError));
+
+            // Inline commands are also limited to 64KB, without a newline
+            let mut parser = inline_parser();
+            parser.read(&vec![b'a'; INLINE_MAX_SIZE])?;
+            let error = parser.read(b"a").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::SizeExceededError));
+            Ok(())
+        }
+    }
+
     mod simple_string {
         use super::*;
 
@@ -756,4 +1287,652 @@ mod tests {
             assert_num_results(&results, 1);
         }
     }
+
+    mod null {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"_\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], Nil);
+        }
+
+        #[test]
+        fn invalid() {
+            test_parser_err(b"_x\r\n");
+        }
+    }
+
+    mod boolean {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"#t\r\n#f\r\n");
+
+            assert_num_results(&results, 2);
+            assert_eq!(results[0], Boolean(true));
+            assert_eq!(results[1], Boolean(false));
+        }
+
+        #[test]
+        fn invalid() {
+            test_parser_err(b"#x\r\n");
+        }
+    }
+
+    mod double {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b",1.23\r\n,-10\r\n,1.5e3\r\n");
+
+            assert_num_results(&results, 3);
+            assert_eq!(results[0], Double(1.23));
+            assert_eq!(results[1], Double(-10.0));
+            assert_eq!(results[2], Double(1500.0));
+        }
+
+        #[test]
+        fn special() {
+            let results = test_parser_ok(b",inf\r\n,-inf\r\n,nan\r\n");
+
+            assert_num_results(&results, 3);
+            assert_eq!(results[0], Double(f64::INFINITY));
+            assert_eq!(results[1], Double(f64::NEG_INFINITY));
+            match results[2] {
+                Double(double) => assert!(double.is_nan()),
+                _ => panic!("Expected Double variant"),
+            }
+        }
+
+        #[test]
+        fn invalid() {
+            test_parser_err(b",one\r\n");
+        }
+    }
+
+    mod big_number {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"(3492890328409238509324850943850943825024385\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                BigNumber("3492890328409238509324850943850943825024385".into())
+            );
+        }
+
+        #[test]
+        fn negative() {
+            let results = test_parser_ok(b"(-12345678901234567890\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], BigNumber("-12345678901234567890".into()));
+        }
+
+        #[test]
+        fn invalid() {
+            test_parser_err(b"(12e4\r\n");
+        }
+    }
+
+    mod map {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"%2\r\n+first\r\n:1\r\n+second\r\n:2\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Map(vec![
+                    (SimpleString("first".into()), Integer(1)),
+                    (SimpleString("second".into()), Integer(2)),
+                ])
+            );
+        }
+
+        #[test]
+        fn aggregate_keys() {
+            let results = test_parser_ok(b"%1\r\n*2\r\n:1\r\n:2\r\n~1\r\n#t\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Map(vec![(
+                    Array(vec![Integer(1), Integer(2)]),
+                    Set(vec![Boolean(true)])
+                )])
+            );
+        }
+
+        #[test]
+        fn empty() {
+            let results = test_parser_ok(b"%0\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], Map(Vec::new()));
+        }
+
+        #[test]
+        fn incomplete_pair() {
+            let results = test_parser_ok(b"%1\r\n+key\r\n");
+
+            assert_empty_result(results);
+        }
+
+        #[test]
+        fn onebyte() -> Result<()> {
+            let mut parser = RespParser::default();
+            let input = b"%2\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n%1\r\n_\r\n_\r\n";
+            let (last, rest) = input.split_last().unwrap();
+            for byte in rest {
+                assert_empty_result(parser.read(&[*byte])?);
+            }
+
+            let results = parser.read(&[*last])?;
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Map(vec![
+                    (BulkString("a".into()), Integer(1)),
+                    (BulkString("b".into()), Map(vec![(Nil, Nil)])),
+                ])
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn null() {
+            test_parser_err(b"%-1\r\n");
+        }
+    }
+
+    mod set {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"~3\r\n+a\r\n+b\r\n:3\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Set(vec![
+                    SimpleString("a".into()),
+                    SimpleString("b".into()),
+                    Integer(3)
+                ])
+            );
+        }
+
+        #[test]
+        fn empty() {
+            let results = test_parser_ok(b"~0\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], Set(Vec::new()));
+        }
+
+        #[test]
+        fn null() {
+            test_parser_err(b"~-1\r\n");
+        }
+    }
+
+    mod push {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b">3\r\n+message\r\n+channel\r\n$5\r\nhello\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Push(vec![
+                    SimpleString("message".into()),
+                    SimpleString("channel".into()),
+                    BulkString("hello".into())
+                ])
+            );
+        }
+
+        #[test]
+        fn null() {
+            test_parser_err(b">-1\r\n");
+        }
+
+        #[test]
+        fn read_split() -> Result<()> {
+            let mut parser = RespParser::default();
+            let input = b"+OK\r\n>2\r\n+invalidate\r\n*1\r\n$3\r\nkey\r\n:1\r\n>1\r\n+p";
+            let (replies, pushes) = parser.read_split(input)?;
+
+            assert_eq!(replies, vec![SimpleString("OK".into()), Integer(1)]);
+            assert_eq!(
+                pushes,
+                vec![Push(vec![
+                    SimpleString("invalidate".into()),
+                    Array(vec![BulkString("key".into())])
+                ])]
+            );
+
+            let (replies, pushes) = parser.read_split(b"ong\r\n")?;
+            assert_empty_result(replies);
+            assert_eq!(pushes, vec![Push(vec![SimpleString("pong".into())])]);
+            Ok(())
+        }
+    }
+
+    mod attribute {
+        use super::*;
+
+        fn popularity() -> Vec<(RespType, RespType)> {
+            vec![(
+                SimpleString("key-popularity".into()),
+                Map(vec![(BulkString("a".into()), Double(0.1923))]),
+            )]
+        }
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(
+                b"|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n*1\r\n:2039123\r\n",
+            );
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Attribute {
+                    attributes: popularity(),
+                    reply: Box::new(Array(vec![Integer(2039123)])),
+                }
+            );
+        }
+
+        #[test]
+        fn nested() {
+            let results = test_parser_ok(b"*2\r\n:1\r\n|1\r\n+ttl\r\n:3600\r\n:2\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Array(vec![
+                    Integer(1),
+                    Attribute {
+                        attributes: vec![(SimpleString("ttl".into()), Integer(3600))],
+                        reply: Box::new(Integer(2)),
+                    }
+                ])
+            );
+        }
+
+        #[test]
+        fn empty() {
+            let results = test_parser_ok(b"|0\r\n+OK\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Attribute {
+                    attributes: Vec::new(),
+                    reply: Box::new(SimpleString("OK".into())),
+                }
+            );
+        }
+
+        #[test]
+        fn incomplete_reply() -> Result<()> {
+            let mut parser = RespParser::default();
+            assert_empty_result(parser.read(b"|1\r\n+ttl\r\n:3600\r\n$5\r\nhel")?);
+
+            let results = parser.read(b"lo\r\n")?;
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Attribute {
+                    attributes: vec![(SimpleString("ttl".into()), Integer(3600))],
+                    reply: Box::new(BulkString("hello".into())),
+                }
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn discarded() -> Result<()> {
+            let mut parser = RespParser::new(RespConfig {
+                discard_attributes: true,
+                ..Default::default()
+            });
+            let results = parser
+                .read(b"|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n*1\r\n:2039123\r\n")?;
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], Array(vec![Integer(2039123)]));
+            Ok(())
+        }
+
+        #[test]
+        fn null() {
+            test_parser_err(b"|-1\r\n+OK\r\n");
+        }
+    }
+
+    mod streamed {
+        use super::*;
+
+        #[test]
+        fn string() {
+            let results = test_parser_ok(b"$?\r\n;4\r\nHell\r\n;5\r\no wor\r\n;1\r\nd\r\n;0\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], BulkString("Hello word".into()));
+        }
+
+        #[test]
+        fn empty_string() {
+            let results = test_parser_ok(b"$?\r\n;0\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], BulkString("".into()));
+        }
+
+        #[test]
+        fn string_onebyte() -> Result<()> {
+            let mut parser = RespParser::default();
+            let input = b"$?\r\n;4\r\nHell\r\n;10\r\no\r\n world!\r\n;0\r\n";
+            let (last, rest) = input.split_last().unwrap();
+            for byte in rest {
+                assert_empty_result(parser.read(&[*byte])?);
+            }
+
+            let results = parser.read(&[*last])?;
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], BulkString("Hello\r\n world!".into()));
+            Ok(())
+        }
+
+        #[test]
+        fn string_size_exceeded() {
+            let mut parser = RespParser::new(RespConfig::new(8, 1024));
+            let result = parser.read(b"$?\r\n;4\r\nHell\r\n;5\r\no wor\r\n");
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn invalid_chunk() {
+            test_parser_err(b"$?\r\n:4\r\nHell\r\n");
+            test_parser_err(b"$?\r\n;?\r\nHell\r\n");
+        }
+
+        #[test]
+        fn array() {
+            let results = test_parser_ok(b"*?\r\n:1\r\n:2\r\n*?\r\n+a\r\n.\r\n.\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Array(vec![
+                    Integer(1),
+                    Integer(2),
+                    Array(vec![SimpleString("a".into())])
+                ])
+            );
+        }
+
+        #[test]
+        fn empty_array() {
+            let results = test_parser_ok(b"*?\r\n.\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], Array(Vec::new()));
+        }
+
+        #[test]
+        fn map() {
+            let results = test_parser_ok(b"%?\r\n+a\r\n:1\r\n+b\r\n$?\r\n;2\r\nhi\r\n;0\r\n.\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Map(vec![
+                    (SimpleString("a".into()), Integer(1)),
+                    (SimpleString("b".into()), BulkString("hi".into()))
+                ])
+            );
+        }
+
+        #[test]
+        fn map_missing_value() {
+            test_parser_err(b"%?\r\n+a\r\n.\r\n");
+        }
+
+        #[test]
+        fn set_onebyte() -> Result<()> {
+            let mut parser = RespParser::default();
+            let input = b"~?\r\n+a\r\n%?\r\n.\r\n.\r\n";
+            let (last, rest) = input.split_last().unwrap();
+            for byte in rest {
+                assert_empty_result(parser.read(&[*byte])?);
+            }
+
+            let results = parser.read(&[*last])?;
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                Set(vec![SimpleString("a".into()), Map(Vec::new())])
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn aggregate_size_exceeded() {
+            let mut parser = RespParser::new(RespConfig::new(2, 1024));
+            let result = parser.read(b"*?\r\n:1\r\n:2\r\n:3\r\n.\r\n");
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn not_streamable() {
+            test_parser_err(b">?\r\n+a\r\n.\r\n");
+            test_parser_err(b"|?\r\n+a\r\n+b\r\n.\r\n+OK\r\n");
+        }
+    }
+
+    mod verbatim_string {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"=15\r\ntxt:Some string\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                VerbatimString {
+                    format: "txt".into(),
+                    text: "Some string".into()
+                }
+            );
+        }
+
+        #[test]
+        fn empty_text() {
+            let results = test_parser_ok(b"=4\r\nmkd:\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(
+                results[0],
+                VerbatimString {
+                    format: "mkd".into(),
+                    text: Vec::new()
+                }
+            );
+        }
+
+        #[test]
+        fn round_trip() {
+            let input = b"*2\r\n=15\r\ntxt:Some string\r\n!10\r\nERR failed\r\n";
+            let mut results = test_parser_ok(input);
+
+            assert_num_results(&results, 1);
+            assert_eq!(results.remove(0).as_bytes(), input);
+        }
+
+        #[test]
+        fn missing_format() {
+            test_parser_err(b"=3\r\ntxt\r\n");
+            test_parser_err(b"=5\r\nhello\r\n");
+        }
+
+        #[test]
+        fn null() {
+            test_parser_err(b"=-1\r\n");
+        }
+    }
+
+    mod blob_error {
+        use super::*;
+
+        #[test]
+        fn valid() {
+            let results = test_parser_ok(b"!21\r\nSYNTAX invalid syntax\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], BlobError("SYNTAX invalid syntax".into()));
+            assert!(results[0].is_error());
+        }
+
+        #[test]
+        fn binary() {
+            let results = test_parser_ok(b"!4\r\nE\r\n\0\r\n");
+
+            assert_num_results(&results, 1);
+            assert_eq!(results[0], BlobError(b"E\r\n\0".to_vec()));
+        }
+
+        #[test]
+        fn null() {
+            test_parser_err(b"!-1\r\n");
+        }
+
+        #[test]
+        fn streamed() {
+            test_parser_err(b"!?\r\n;1\r\nE\r\n;0\r\n");
+        }
+    }
+
+    mod errors {
+        use super::*;
+
+        fn test_parser_error(buffer: &[u8]) -> RespError {
+            let mut parser = RespParser::default();
+            match parser.read(buffer) {
+                Err(error) => error,
+                other => panic!("result was not Err(), was {:#?}", other),
+            }
+        }
+
+        #[test]
+        fn type_token() {
+            let error = test_parser_error(b"+OK\r\nx\r\n");
+            assert!(matches!(error.kind(), ParserError::TypeTokenError('x')));
+            assert_eq!(error.offset(), Some(5));
+        }
+
+        #[test]
+        fn resp2_only() {
+            let mut parser = RespParser::new(RespConfig {
+                protocol: Protocol::Resp2,
+                ..Default::default()
+            });
+            let error = parser.read(b"*2\r\n:1\r\n%0\r\n").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::TypeTokenError('%')));
+            assert_eq!(error.offset(), Some(8));
+        }
+
+        #[test]
+        fn invalid_integer() {
+            let error = test_parser_error(b"*2\r\n:1\r\n:12a\r\n");
+            assert!(matches!(error.kind(), ParserError::IntegerError(line) if line == "12a"));
+            assert_eq!(error.offset(), Some(9));
+        }
+
+        #[test]
+        fn invalid_size() {
+            let error = test_parser_error(b"$x\r\n");
+            assert!(matches!(error.kind(), ParserError::IntegerError(_)));
+            assert_eq!(error.offset(), Some(1));
+        }
+
+        #[test]
+        fn invalid_utf8() {
+            let error = test_parser_error(b"+ab\xff\r\n");
+            assert!(matches!(error.kind(), ParserError::Utf8Error(_)));
+            assert_eq!(error.offset(), Some(3));
+        }
+
+        #[test]
+        fn size_exceeded() {
+            let mut parser = RespParser::new(RespConfig::new(4, 1024));
+            let error = parser.read(b"+OK\r\n$5\r\nhello\r\n").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::SizeExceededError));
+            assert_eq!(error.offset(), Some(6));
+        }
+
+        #[test]
+        fn buffer_exceeded() {
+            let mut parser = RespParser::new(RespConfig::new(1024, 8));
+            let error = parser.read(b"$10\r\nhello").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::SizeExceededError));
+        }
+
+        #[test]
+        fn depth_exceeded() {
+            let mut parser = RespParser::default();
+            parser.config.max_depth = 2;
+            assert_eq!(
+                parser.read(b"*1\r\n*1\r\n:1\r\n").unwrap(),
+                vec![Array(vec![Array(vec![Integer(1)])])]
+            );
+
+            let error = parser.read(b"*1\r\n*1\r\n*1\r\n:1\r\n").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::DepthExceededError));
+            assert_eq!(error.offset(), Some(20));
+        }
+
+        #[test]
+        fn attribute_depth_exceeded() {
+            let mut payload = b"|0\r\n".repeat(200_000);
+            payload.extend_from_slice(b"+OK\r\n");
+            let error = RespParser::default().read(&payload).unwrap_err();
+            assert!(matches!(error.kind(), ParserError::DepthExceededError));
+        }
+
+        #[test]
+        fn offset_across_reads() -> Result<()> {
+            let mut parser = RespParser::default();
+            assert_eq!(parser.read(b"+OK\r\n:1")?, vec![SimpleString("OK".into())]);
+            assert_eq!(parser.read(b"\r\n*2\r\n")?, vec![Integer(1)]);
+
+            let error = parser.read(b"#t\r\n#x\r\n").unwrap_err();
+            assert!(matches!(error.kind(), ParserError::ValueError(_)));
+            assert_eq!(error.offset(), Some(18));
+
+            // Offsets keep counting from the start of the stream after an error
+            assert_eq!(parser.read(b":2\r\n")?, vec![Integer(2)]);
+            let error = parser.read(b"?").unwrap_err();
+            assert_eq!(error.offset(), Some(25));
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,184 @@
+use super::connection::check_reply;
+use super::error::{ClientResult, Result};
+use super::RespParser;
+use super::RespType;
+
+/// Collects commands to be sent together, encoded into a single buffer
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<RespType>,
+}
+
+impl Pipeline {
+    /// Creates an empty pipeline
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command, see [`RespType::command`]
+    pub fn command(&mut self, command: Vec<Vec<u8>>) -> &mut Self {
+        self.commands.push(RespType::command(command));
+        self
+    }
+
+    /// Number of commands, which is also the number of replies expected
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Checks if no commands were added
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Encodes all commands, in order, into one buffer sized up front
+    pub fn encode(&self) -> Vec<u8> {
+        let length = self.commands.iter().map(RespType::encoded_len).sum();
+        let mut buffer = Vec::with_capacity(length);
+        for command in &self.commands {
+            command
+                .encode(&mut buffer)
+                .expect("writing to a Vec can not fail");
+        }
+        buffer
+    }
+
+    /// Creates a collector for this pipeline's replies
+    pub fn replies(&self) -> PipelineReplies {
+        PipelineReplies {
+            expected: self.len(),
+            replies: Vec::with_capacity(self.len()),
+            pushes: Vec::new(),
+        }
+    }
+}
+
+/// Gathers the replies to a [`Pipeline`] from a [`RespParser`], taking no more
+/// frames than there are commands so anything read after them stays buffered
+/// in the parser
+#[derive(Debug)]
+pub struct PipelineReplies {
+    expected: usize,
+    replies: Vec<RespType>,
+    pushes: Vec<RespType>,
+}
+
+impl PipelineReplies {
+    /// Feeds data read from the server through the parser, returns `true`
+    /// once all replies have arrived. RESP3 pushes are kept aside and do not
+    /// count as replies, data past the last reply is left in the parser.
+    pub fn read(&mut self, parser: &mut RespParser, buffer: &[u8]) -> Result<bool> {
+        let mut buffer = buffer;
+        loop {
+            let remaining = self.expected.saturating_sub(self.replies.len());
+            let items = parser.read_limited(buffer, remaining)?;
+            if items.is_empty() {
+                break;
+            }
+            // Pushes take up part of the limit, the replies they left unread
+            // are parsed from the parser's own buffer
+            buffer = &[];
+            for item in items {
+                match item.is_push() {
+                    true => self.pushes.push(item),
+                    false => self.replies.push(item),
+                }
+            }
+        }
+        Ok(self.is_complete())
+    }
+
+    /// Checks if all replies have arrived
+    pub fn is_complete(&self) -> bool {
+        self.replies.len() >= self.expected
+    }
+
+    /// RESP3 pushes that arrived in between the replies
+    pub fn pushes(&self) -> &[RespType] {
+        &self.pushes
+    }
+
+    /// The replies in command order, each error reply is returned as its own
+    /// [`ClientError::ReplyError`](crate::ClientError::ReplyError) without
+    /// affecting the others
+    pub fn into_results(self) -> Vec<ClientResult<RespType>> {
+        self.replies.into_iter().map(check_reply).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientError;
+    use RespType::*;
+
+    fn pipeline() -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        pipeline
+            .command(vec!["SET".into(), "a".into(), "1".into()])
+            .command(vec!["INCR".into(), "b".into()])
+            .command(vec!["GET".into(), "a".into()]);
+        pipeline
+    }
+
+    #[test]
+    fn encode() {
+        let pipeline = pipeline();
+        let encoded = pipeline.encode();
+        assert_eq!(
+            encoded,
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*2\r\n$4\r\nINCR\r\n$1\r\nb\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\n"
+        );
+        assert_eq!(encoded.len(), encoded.capacity());
+    }
+
+    #[test]
+    fn split_replies() -> Result<()> {
+        let pipeline = pipeline();
+        let mut parser = RespParser::default();
+        let mut replies = pipeline.replies();
+        let data = b"+OK\r\n-ERR value is not an integer\r\n$1\r\n1\r\n:5\r\n";
+        let (first, rest) = data.split_at(12);
+
+        assert!(!replies.read(&mut parser, first)?);
+        assert!(replies.read(&mut parser, rest)?);
+
+        // The frame after the pipeline's replies is left in the parser
+        assert_eq!(parser.read(b"")?, vec![Integer(5)]);
+
+        let results = replies.into_results();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &SimpleString("OK".into()));
+        assert!(
+            matches!(&results[1], Err(ClientError::ReplyError(error)) if error.code.as_str() == "ERR")
+        );
+        assert_eq!(results[2].as_ref().unwrap(), &BulkString("1".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn byte_at_a_time() -> Result<()> {
+        let pipeline = pipeline();
+        let mut parser = RespParser::default();
+        let mut replies = pipeline.replies();
+        for byte in b"+OK\r\n>2\r\n+message\r\n+hi\r\n:1\r\n$-1\r\n" {
+            replies.read(&mut parser, &[*byte])?;
+        }
+        assert!(replies.is_complete());
+        assert_eq!(replies.pushes().len(), 1);
+        assert_eq!(replies.into_results().len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn pushes_between_replies() -> Result<()> {
+        let pipeline = pipeline();
+        let mut parser = RespParser::default();
+        let mut replies = pipeline.replies();
+        let data = b">1\r\n+a\r\n+OK\r\n>1\r\n+b\r\n:1\r\n$-1\r\n:7\r\n";
+        assert!(replies.read(&mut parser, data)?);
+        assert_eq!(replies.pushes().len(), 2);
+        assert_eq!(parser.read(b"")?, vec![Integer(7)]);
+        Ok(())
+    }
+}
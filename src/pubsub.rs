@@ -0,0 +1,494 @@
+use std::collections::{HashSet, VecDeque};
+
+use super::connection::check_reply;
+use super::error::{ClientError, ClientResult};
+use super::Connection;
+use super::RespType;
+
+/// The kind of subscription, each has its own subscribe command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    /// Channels subscribed with `SUBSCRIBE`
+    Channel,
+    /// Glob-style patterns subscribed with `PSUBSCRIBE`
+    Pattern,
+    /// Shard channels subscribed with `SSUBSCRIBE`
+    Shard,
+}
+
+impl SubscriptionKind {
+    fn subscribe(&self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "SUBSCRIBE",
+            SubscriptionKind::Pattern => "PSUBSCRIBE",
+            SubscriptionKind::Shard => "SSUBSCRIBE",
+        }
+    }
+
+    fn unsubscribe(&self) -> &'static str {
+        match self {
+            SubscriptionKind::Channel => "UNSUBSCRIBE",
+            SubscriptionKind::Pattern => "PUNSUBSCRIBE",
+            SubscriptionKind::Shard => "SUNSUBSCRIBE",
+        }
+    }
+}
+
+/// A message published to a subscribed channel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The subscription the message was delivered through
+    pub kind: SubscriptionKind,
+    /// The pattern that matched the channel, for pattern subscriptions
+    pub pattern: Option<Vec<u8>>,
+    /// The channel the message was published to
+    pub channel: Vec<u8>,
+    /// The published data
+    pub payload: Vec<u8>,
+}
+
+/// Frames sent by the server to a subscribed connection, decoded from RESP2
+/// arrays or RESP3 pushes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PubSubEvent {
+    /// A published message
+    Message(Message),
+    /// Confirmation of a subscription, with the number of subscriptions held
+    Subscribed {
+        kind: SubscriptionKind,
+        channel: Vec<u8>,
+        count: usize,
+    },
+    /// Confirmation of an unsubscription, with the number of subscriptions
+    /// left. The channel is `None` when unsubscribing from nothing.
+    Unsubscribed {
+        kind: SubscriptionKind,
+        channel: Option<Vec<u8>>,
+        count: usize,
+    },
+}
+
+impl TryFrom<RespType> for PubSubEvent {
+    type Error = ClientError;
+
+    fn try_from(resp: RespType) -> ClientResult<Self> {
+        let resp = check_reply(resp)?;
+        let event = match &resp {
+            RespType::Array(elements) | RespType::Push(elements) => decode(elements),
+            _ => None,
+        };
+        match event {
+            Some(event) => Ok(event),
+            None => Err(ClientError::UnexpectedReplyError(resp)),
+        }
+    }
+}
+
+fn decode(elements: &[RespType]) -> Option<PubSubEvent> {
+    let (name, rest) = elements.split_first()?;
+    let event = match (bytes(name)?, rest) {
+        (b"message", [channel, payload]) => {
+            message(SubscriptionKind::Channel, None, channel, payload)?
+        }
+        (b"smessage", [channel, payload]) => {
+            message(SubscriptionKind::Shard, None, channel, payload)?
+        }
+        (b"pmessage", [pattern, channel, payload]) => {
+            message(SubscriptionKind::Pattern, Some(pattern), channel, payload)?
+        }
+        (name, [channel, RespType::Integer(count)]) => {
+            let count = usize::try_from(*count).ok()?;
+            let channel = bytes(channel).map(<[u8]>::to_vec);
+            let (kind, subscribed) = match name {
+                b"subscribe" => (SubscriptionKind::Channel, true),
+                b"psubscribe" => (SubscriptionKind::Pattern, true),
+                b"ssubscribe" => (SubscriptionKind::Shard, true),
+                b"unsubscribe" => (SubscriptionKind::Channel, false),
+                b"punsubscribe" => (SubscriptionKind::Pattern, false),
+                b"sunsubscribe" => (SubscriptionKind::Shard, false),
+                _ => return None,
+            };
+            match subscribed {
+                true => PubSubEvent::Subscribed {
+                    kind,
+                    channel: channel?,
+                    count,
+                },
+                false => PubSubEvent::Unsubscribed {
+                    kind,
+                    channel,
+                    count,
+                },
+            }
+        }
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn bytes(resp: &RespType) -> Option<&[u8]> {
+    match resp {
+        RespType::BulkString(bytes) => Some(bytes),
+        RespType::SimpleString(string) => Some(string.as_bytes()),
+        _ => None,
+    }
+}
+
+fn message(
+    kind: SubscriptionKind,
+    pattern: Option<&RespType>,
+    channel: &RespType,
+    payload: &RespType,
+) -> Option<PubSubEvent> {
+    let pattern = match pattern {
+        Some(pattern) => Some(bytes(pattern)?.to_vec()),
+        None => None,
+    };
+    Some(PubSubEvent::Message(Message {
+        kind,
+        pattern,
+        channel: bytes(channel)?.to_vec(),
+        payload: bytes(payload)?.to_vec(),
+    }))
+}
+
+/// A connection in subscriber mode, see [`Subscriber::next_message`].
+///
+/// Subscribing waits for the server's confirmations, any messages arriving
+/// in the meantime are kept for [`Subscriber::next_message`].
+pub struct Subscriber {
+    connection: Connection,
+    channels: HashSet<Vec<u8>>,
+    patterns: HashSet<Vec<u8>>,
+    shards: HashSet<Vec<u8>>,
+    count: usize,
+    shard_count: usize,
+    messages: VecDeque<Message>,
+}
+
+impl Subscriber {
+    /// Wraps a connection, which should not be used for anything else
+    pub fn new(connection: Connection) -> Self {
+        Subscriber {
+            connection,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shards: HashSet::new(),
+            count: 0,
+            shard_count: 0,
+            messages: VecDeque::new(),
+        }
+    }
+
+    /// Subscribes to channels with `SUBSCRIBE`
+    pub fn subscribe(&mut self, channels: Vec<Vec<u8>>) -> ClientResult<()> {
+        self.change(SubscriptionKind::Channel, true, channels)
+    }
+
+    /// Subscribes to glob-style patterns with `PSUBSCRIBE`
+    pub fn psubscribe(&mut self, patterns: Vec<Vec<u8>>) -> ClientResult<()> {
+        self.change(SubscriptionKind::Pattern, true, patterns)
+    }
+
+    /// Subscribes to shard channels with `SSUBSCRIBE`
+    pub fn ssubscribe(&mut self, channels: Vec<Vec<u8>>) -> ClientResult<()> {
+        self.change(SubscriptionKind::Shard, true, channels)
+    }
+
+    /// Unsubscribes from channels, or from all channels if none are given
+    pub fn unsubscribe(&mut self, channels: Vec<Vec<u8>>) -> ClientResult<()> {
+        self.change(SubscriptionKind::Channel, false, channels)
+    }
+
+    /// Unsubscribes from patterns, or from all patterns if none are given
+    pub fn punsubscribe(&mut self, patterns: Vec<Vec<u8>>) -> ClientResult<()> {
+        self.change(SubscriptionKind::Pattern, false, patterns)
+    }
+
+    /// Unsubscribes from shard channels, or from all of them if none are given
+    pub fn sunsubscribe(&mut self, channels: Vec<Vec<u8>>) -> ClientResult<()> {
+        self.change(SubscriptionKind::Shard, false, channels)
+    }
+
+    /// Number of subscriptions held, as last confirmed by the server
+    pub fn subscriptions(&self) -> usize {
+        self.count + self.shard_count
+    }
+
+    /// Channels, patterns or shard channels currently subscribed to
+    pub fn subscribed(&self, kind: SubscriptionKind) -> &HashSet<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Channel => &self.channels,
+            SubscriptionKind::Pattern => &self.patterns,
+            SubscriptionKind::Shard => &self.shards,
+        }
+    }
+
+    /// Waits for the next published message
+    pub fn next_message(&mut self) -> ClientResult<Message> {
+        loop {
+            if let Some(message) = self.messages.pop_front() {
+                return Ok(message);
+            }
+            self.receive()?;
+        }
+    }
+
+    /// Returns the connection, which is still in subscriber mode unless all
+    /// subscriptions were removed
+    pub fn into_connection(self) -> Connection {
+        self.connection
+    }
+
+    fn change(
+        &mut self,
+        kind: SubscriptionKind,
+        subscribe: bool,
+        channels: Vec<Vec<u8>>,
+    ) -> ClientResult<()> {
+        // One confirmation is sent per channel, or per subscription when
+        // unsubscribing from all of them, and at least one in any case
+        let mut expected = match channels.is_empty() {
+            true => self.subscribed(kind).len().max(1),
+            false => channels.len(),
+        };
+        let name = match subscribe {
+            true => kind.subscribe(),
+            false => kind.unsubscribe(),
+        };
+        let mut command = vec![name.into()];
+        command.extend(channels);
+        self.connection.send(&RespType::command(command))?;
+
+        while expected > 0 {
+            match self.receive()? {
+                Some(PubSubEvent::Subscribed {
+                    kind: confirmed, ..
+                }) if subscribe && confirmed == kind => expected -= 1,
+                Some(PubSubEvent::Unsubscribed {
+                    kind: confirmed, ..
+                }) if !subscribe && confirmed == kind => expected -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads one event, updating the subscriptions or queueing the message.
+    /// Other pushes, such as client tracking invalidations, are skipped.
+    fn receive(&mut self) -> ClientResult<Option<PubSubEvent>> {
+        let frame = self.connection.receive()?;
+        let event = match frame.is_push() {
+            true => match PubSubEvent::try_from(frame) {
+                Ok(event) => event,
+                Err(_) => return Ok(None),
+            },
+            false => PubSubEvent::try_from(frame)?,
+        };
+        match &event {
+            PubSubEvent::Message(message) => self.messages.push_back(message.clone()),
+            PubSubEvent::Subscribed {
+                kind,
+                channel,
+                count,
+            } => {
+                self.set_count(*kind, *count);
+                self.subscribed_mut(*kind).insert(channel.clone());
+            }
+            PubSubEvent::Unsubscribed {
+                kind,
+                channel,
+                count,
+            } => {
+                self.set_count(*kind, *count);
+                if let Some(channel) = channel {
+                    self.subscribed_mut(*kind).remove(channel);
+                }
+            }
+        }
+        Ok(Some(event))
+    }
+
+    fn set_count(&mut self, kind: SubscriptionKind, count: usize) {
+        match kind {
+            SubscriptionKind::Shard => self.shard_count = count,
+            _ => self.count = count,
+        }
+    }
+
+    fn subscribed_mut(&mut self, kind: SubscriptionKind) -> &mut HashSet<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shards,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::serve_raw;
+    use crate::ConnectionConfig;
+    use RespType::*;
+
+    fn bulk(data: &str) -> RespType {
+        BulkString(data.into())
+    }
+
+    mod decode {
+        use super::*;
+
+        #[test]
+        fn message() -> ClientResult<()> {
+            let resp = Array(vec![bulk("message"), bulk("news"), bulk("hello")]);
+            let expected = Message {
+                kind: SubscriptionKind::Channel,
+                pattern: None,
+                channel: "news".into(),
+                payload: "hello".into(),
+            };
+            assert_eq!(PubSubEvent::try_from(resp)?, PubSubEvent::Message(expected));
+            Ok(())
+        }
+
+        #[test]
+        fn pmessage() -> ClientResult<()> {
+            let resp = Array(vec![
+                bulk("pmessage"),
+                bulk("n*"),
+                bulk("news"),
+                bulk("hello"),
+            ]);
+            match PubSubEvent::try_from(resp)? {
+                PubSubEvent::Message(message) => {
+                    assert_eq!(message.kind, SubscriptionKind::Pattern);
+                    assert_eq!(message.pattern, Some("n*".into()));
+                    assert_eq!(message.channel, b"news");
+                }
+                other => panic!("expected a message, got {:?}", other),
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn push() -> ClientResult<()> {
+            let resp = Push(vec![bulk("smessage"), bulk("orders"), bulk("1")]);
+            match PubSubEvent::try_from(resp)? {
+                PubSubEvent::Message(message) => {
+                    assert_eq!(message.kind, SubscriptionKind::Shard);
+                    assert_eq!(message.payload, b"1");
+                }
+                other => panic!("expected a message, got {:?}", other),
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn confirmations() -> ClientResult<()> {
+            let resp = Push(vec![bulk("psubscribe"), bulk("n*"), Integer(2)]);
+            let expected = PubSubEvent::Subscribed {
+                kind: SubscriptionKind::Pattern,
+                channel: "n*".into(),
+                count: 2,
+            };
+            assert_eq!(PubSubEvent::try_from(resp)?, expected);
+
+            let resp = Array(vec![bulk("unsubscribe"), Null, Integer(0)]);
+            let expected = PubSubEvent::Unsubscribed {
+                kind: SubscriptionKind::Channel,
+                channel: None,
+                count: 0,
+            };
+            assert_eq!(PubSubEvent::try_from(resp)?, expected);
+            Ok(())
+        }
+
+        #[test]
+        fn invalid() {
+            for resp in [
+                Array(vec![bulk("message"), bulk("news")]),
+                Array(vec![bulk("subscribe"), bulk("news"), bulk("1")]),
+                Array(vec![]),
+                SimpleString("OK".into()),
+            ] {
+                let result = PubSubEvent::try_from(resp);
+                assert!(matches!(result, Err(ClientError::UnexpectedReplyError(_))));
+            }
+            let result = PubSubEvent::try_from(Error("ERR wrong".into()));
+            assert!(matches!(result, Err(ClientError::ReplyError(_))));
+        }
+    }
+
+    fn encode(frames: Vec<RespType>) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for frame in frames {
+            frame.encode(&mut buffer).unwrap();
+        }
+        buffer
+    }
+
+    #[test]
+    fn subscriber() -> ClientResult<()> {
+        let addr = serve_raw(|command| {
+            let name = String::from_utf8(command[0].clone())
+                .unwrap()
+                .to_lowercase();
+            let mut frames = Vec::new();
+            match name.as_str() {
+                "subscribe" => {
+                    // A message published before the second confirmation
+                    frames.push(Array(vec![bulk("subscribe"), bulk("a"), Integer(1)]));
+                    frames.push(Array(vec![bulk("message"), bulk("a"), bulk("early")]));
+                    frames.push(Array(vec![bulk("subscribe"), bulk("b"), Integer(2)]));
+                    frames.push(Array(vec![bulk("message"), bulk("b"), bulk("late")]));
+                }
+                "psubscribe" => {
+                    frames.push(Array(vec![bulk("psubscribe"), bulk("c*"), Integer(3)]));
+                }
+                "ssubscribe" => {
+                    frames.push(Array(vec![bulk("ssubscribe"), bulk("s"), Integer(1)]));
+                }
+                "unsubscribe" => {
+                    frames.push(Array(vec![bulk("unsubscribe"), bulk("a"), Integer(2)]));
+                    frames.push(Array(vec![bulk("unsubscribe"), bulk("b"), Integer(1)]));
+                }
+                _ => frames.push(Error("ERR unexpected".into())),
+            }
+            encode(frames)
+        });
+        let connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        let mut subscriber = Subscriber::new(connection);
+
+        subscriber.subscribe(vec!["a".into(), "b".into()])?;
+        assert_eq!(subscriber.subscriptions(), 2);
+        subscriber.psubscribe(vec!["c*".into()])?;
+        subscriber.ssubscribe(vec!["s".into()])?;
+        assert_eq!(subscriber.subscriptions(), 4);
+
+        assert_eq!(subscriber.next_message()?.payload, b"early");
+        assert_eq!(subscriber.next_message()?.payload, b"late");
+
+        subscriber.unsubscribe(vec![])?;
+        assert_eq!(subscriber.subscriptions(), 2);
+        assert!(subscriber.subscribed(SubscriptionKind::Channel).is_empty());
+        assert_eq!(subscriber.subscribed(SubscriptionKind::Pattern).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn other_pushes() -> ClientResult<()> {
+        let addr = serve_raw(|_| {
+            encode(vec![
+                Push(vec![bulk("subscribe"), bulk("a"), Integer(1)]),
+                Push(vec![bulk("invalidate"), Array(vec![bulk("key")])]),
+                Push(vec![bulk("message"), bulk("a"), bulk("hi")]),
+            ])
+        });
+        let connection = Connection::connect_tcp(addr, &ConnectionConfig::default())?;
+        let mut subscriber = Subscriber::new(connection);
+        subscriber.subscribe(vec!["a".into()])?;
+        assert_eq!(subscriber.next_message()?.payload, b"hi");
+        Ok(())
+    }
+}
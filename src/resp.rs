@@ -1,8 +1,11 @@
-use anyhow::{anyhow, Result};
-use bstr::{ByteSlice, ByteVec};
+use super::error::{ParserError, RespError, Result, ServerError};
+use bstr::ByteSlice;
+#[cfg(feature = "bytes")]
+use bytes::BufMut;
+use std::io::{self, Write};
 
 /// Enum for RESP types
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum RespType {
     /// Represents a simple utf8 string, that should not contain \r or \n characters
     SimpleString(String),
@@ -18,54 +21,103 @@ pub enum RespType {
     Null,
     /// Null type that is also an array type, but is not an empty array (wat?)
     NullArray,
+    /// RESP3 map type, ordered key/value pairs where keys may be of any type
+    Map(Vec<(RespType, RespType)>),
+    /// RESP3 set type, an unordered collection of elements
+    Set(Vec<RespType>),
+    /// RESP3 push type, out-of-band data such as pub/sub messages
+    Push(Vec<RespType>),
+    /// RESP3 attributes, metadata sent ahead of the reply they describe
+    Attribute {
+        attributes: Vec<(RespType, RespType)>,
+        reply: Box<RespType>,
+    },
+    /// RESP3 verbatim string, text with a three character format such as `txt` or `mkd`
+    VerbatimString { format: String, text: Vec<u8> },
+    /// RESP3 binary safe error
+    BlobError(Vec<u8>),
+    /// RESP3 null type, replaces the RESP2 null bulk string and null array
+    Nil,
+    /// RESP3 boolean type
+    Boolean(bool),
+    /// RESP3 double precision floating point type, may be infinite or NaN
+    Double(f64),
+    /// RESP3 big number type, an integer outside of the signed 64-bit range
+    BigNumber(String),
 }
 
+#[allow(clippy::extra_unused_lifetimes)]
 impl<'a> RespType {
     /// Encodes the RESP type
     pub fn as_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut bytes)
+            .expect("encoding into a Vec can not fail");
+        bytes
+    }
+
+    /// Encodes the RESP type by reference into any writer, producing the same
+    /// output as [`RespType::as_bytes`]
+    pub fn encode<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
         use RespType::*;
-        let mut bytes = Vec::new();
         match self {
-            SimpleString(string) => {
-                bytes.push_str("+");
-                bytes.push_str(string);
-                bytes.push_str("\r\n")
-            }
-            Error(string) => {
-                bytes.push_char('-');
-                bytes.push_str(string);
-                bytes.push_str("\r\n")
-            }
-            Integer(string) => {
-                bytes.push_char(':');
-                bytes.push_str(string.to_string());
-                bytes.push_str("\r\n")
+            SimpleString(string) => write!(writer, "+{}\r\n", string),
+            Error(string) => write!(writer, "-{}\r\n", string),
+            Integer(integer) => write!(writer, ":{}\r\n", integer),
+            BulkString(string) => encode_blob(writer, '$', &[string]),
+            Array(array) => encode_aggregate(writer, '*', array),
+            Map(map) => encode_pairs(writer, '%', map),
+            Set(set) => encode_aggregate(writer, '~', set),
+            Push(push) => encode_aggregate(writer, '>', push),
+            Attribute { attributes, reply } => {
+                encode_pairs(writer, '|', attributes)?;
+                reply.encode(writer)
             }
-            BulkString(string) => {
-                bytes.push_char('$');
-                bytes.push_str(string.len().to_string());
-                bytes.push_str("\r\n");
-                bytes.push_str(string);
-                bytes.push_str("\r\n")
+            VerbatimString { format, text } => {
+                encode_blob(writer, '=', &[format.as_bytes(), b":", text])
             }
-            Array(array) => {
-                bytes.push_char('*');
-                bytes.push_str(array.len().to_string());
-                bytes.push_str("\r\n");
-                for i in array {
-                    bytes.push_str(i.as_bytes())
-                }
-            }
-            Null => bytes.push_str("$-1\r\n"),
-            NullArray => bytes.push_str("*-1\r\n"),
-        };
-        bytes
+            BlobError(string) => encode_blob(writer, '!', &[string]),
+            Null => writer.write_all(b"$-1\r\n"),
+            NullArray => writer.write_all(b"*-1\r\n"),
+            Nil => writer.write_all(b"_\r\n"),
+            Boolean(true) => writer.write_all(b"#t\r\n"),
+            Boolean(false) => writer.write_all(b"#f\r\n"),
+            Double(double) => write!(writer, ",{}\r\n", format_double(*double)),
+            BigNumber(string) => write!(writer, "({}\r\n", string),
+        }
+    }
+
+    /// Encodes the RESP type by reference into a [`BufMut`], producing the same
+    /// output as [`RespType::as_bytes`]. Like [`BufMut::put_slice`], this panics
+    /// if the buffer runs out of space, see [`RespType::encoded_len`].
+    #[cfg(feature = "bytes")]
+    pub fn encode_to<B: BufMut + ?Sized>(&self, buffer: &mut B) {
+        self.encode(&mut buffer.writer())
+            .expect("buffer ran out of space while encoding");
+    }
+
+    /// Number of bytes the encoded RESP type takes up
+    pub fn encoded_len(&self) -> usize {
+        use RespType::*;
+        match self {
+            SimpleString(string) | Error(string) | BigNumber(string) => string.len() + 3,
+            Integer(integer) => integer.to_string().len() + 3,
+            BulkString(string) | BlobError(string) => blob_len(string.len()),
+            Array(array) | Set(array) | Push(array) => aggregate_len(array.len(), array),
+            Map(map) => pairs_len(map),
+            Attribute { attributes, reply } => pairs_len(attributes) + reply.encoded_len(),
+            VerbatimString { format, text } => blob_len(format.len() + 1 + text.len()),
+            Null | NullArray => 5,
+            Nil => 3,
+            Boolean(_) => 4,
+            Double(double) => format_double(*double).len() + 3,
+        }
     }
 
     /// Create a new SimpleString variant
     pub fn simple_string(string: String) -> Result<Self> {
         if string.contains('\r') || string.contains('\n') {
-            Err(anyhow!("Simple string contains \\r or \\n"))
+            Err(ParserError::ValueError("Simple string contains \\r or \\n".to_string()).into())
         } else {
             Ok(RespType::SimpleString(string))
         }
@@ -74,7 +126,7 @@ impl<'a> RespType {
     /// Create a new Error variant
     pub fn error(string: String) -> Result<Self> {
         if string.contains('\r') || string.contains('\n') {
-            Err(anyhow!("Error type contains \\r or \\n"))
+            Err(ParserError::ValueError("Error type contains \\r or \\n".to_string()).into())
         } else {
             Ok(RespType::Error(string))
         }
@@ -85,16 +137,112 @@ impl<'a> RespType {
         RespType::Integer(int)
     }
 
+    /// Create a new boolean variant
+    pub fn boolean(boolean: bool) -> Self {
+        RespType::Boolean(boolean)
+    }
+
+    /// Create a new double variant
+    pub fn double(double: f64) -> Self {
+        RespType::Double(double)
+    }
+
+    /// Create a new big number variant, must be an optionally signed string of digits
+    pub fn big_number(string: String) -> Result<Self> {
+        if is_big_number(&string) {
+            Ok(RespType::BigNumber(string))
+        } else {
+            Err(
+                ParserError::ValueError("Big number contains invalid characters".to_string())
+                    .into(),
+            )
+        }
+    }
+
     /// Create a new bulk string
     pub fn bulk_string(string: Vec<u8>) -> Self {
         RespType::BulkString(string)
     }
 
+    /// Create a new verbatim string, the format must be three characters
+    pub fn verbatim_string(format: String, text: Vec<u8>) -> Result<Self> {
+        if format.len() != 3 || format.contains([':', '\r', '\n']) {
+            Err(ParserError::ValueError(
+                "Verbatim string format must be three characters".to_string(),
+            )
+            .into())
+        } else {
+            Ok(RespType::VerbatimString { format, text })
+        }
+    }
+
+    /// Create a new blob error
+    pub fn blob_error(string: Vec<u8>) -> Self {
+        RespType::BlobError(string)
+    }
+
     /// Create a new array
     pub fn array(array: Vec<RespType>) -> Self {
         RespType::Array(array)
     }
 
+    /// Create a new map
+    pub fn map(map: Vec<(RespType, RespType)>) -> Self {
+        RespType::Map(map)
+    }
+
+    /// Create a new set
+    pub fn set(set: Vec<RespType>) -> Self {
+        RespType::Set(set)
+    }
+
+    /// Create a new push
+    pub fn push(push: Vec<RespType>) -> Self {
+        RespType::Push(push)
+    }
+
+    /// Attaches RESP3 attributes to a reply
+    pub fn attribute(attributes: Vec<(RespType, RespType)>, reply: RespType) -> Self {
+        RespType::Attribute {
+            attributes,
+            reply: Box::new(reply),
+        }
+    }
+
+    /// Checks if this is an error, either a simple error or a RESP3 blob error
+    pub fn is_error(&self) -> bool {
+        matches!(self, RespType::Error(_) | RespType::BlobError(_))
+    }
+
+    /// Parses a simple or blob error into a [`ServerError`], `None` for any
+    /// other type
+    pub fn server_error(&self) -> Option<ServerError> {
+        match self {
+            RespType::Error(error) => Some(ServerError::parse(error)),
+            RespType::BlobError(error) => Some(ServerError::parse(&String::from_utf8_lossy(error))),
+            _ => None,
+        }
+    }
+
+    /// The text of a simple string, or of a bulk string holding valid UTF-8
+    pub(crate) fn as_text(&self) -> Option<&str> {
+        match self {
+            RespType::BulkString(bytes) => std::str::from_utf8(bytes).ok(),
+            RespType::SimpleString(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Checks if this is a RESP3 push, which is not a reply to any command.
+    /// Attributes are looked through, as a push can carry them too.
+    pub fn is_push(&self) -> bool {
+        match self {
+            RespType::Push(_) => true,
+            RespType::Attribute { reply, .. } => reply.is_push(),
+            _ => false,
+        }
+    }
+
     /// Helper for creating an array of bulk strings (such as used for redis commands)
     pub fn command(command: Vec<Vec<u8>>) -> Self {
         let mut cmd = Vec::new();
@@ -106,7 +254,7 @@ impl<'a> RespType {
 }
 
 impl TryInto<String> for RespType {
-    type Error = anyhow::Error;
+    type Error = RespError;
 
     fn try_into(self) -> Result<String> {
         let result = match self {
@@ -123,19 +271,136 @@ impl TryInto<String> for RespType {
                 }
                 format!("[{}]", converted.join(", "))
             },
+            RespType::Map(map) => {
+                let mut converted = Vec::<String>::new();
+                for (key, value) in map {
+                    let key: String = key.try_into()?;
+                    let value: String = value.try_into()?;
+                    converted.push(format!("{}: {}", key, value))
+                }
+                format!("{{{}}}", converted.join(", "))
+            }
+            RespType::Set(set) => {
+                let mut converted = Vec::<String>::new();
+                for i in set {
+                    converted.push(i.try_into()?)
+                }
+                format!("({})", converted.join(", "))
+            }
+            RespType::Push(push) => {
+                let mut converted = Vec::<String>::new();
+                for i in push {
+                    converted.push(i.try_into()?)
+                }
+                format!(">[{}]", converted.join(", "))
+            }
+            RespType::VerbatimString { text, .. } => std::str::from_utf8(&text)?.to_string(),
+            RespType::BlobError(string) => std::str::from_utf8(&string)?.to_string(),
+            RespType::Attribute { reply, .. } => (*reply).try_into()?,
             RespType::Null => "(null)".to_string(),
             RespType::NullArray => "[null]".to_string(),
+            RespType::Nil => "(nil)".to_string(),
+            RespType::Boolean(boolean) => boolean.to_string(),
+            RespType::Double(double) => format_double(double),
+            RespType::BigNumber(string) => string,
         };
         Ok(result)
     }
 }
 
+fn encode_blob<W: Write + ?Sized>(writer: &mut W, token: char, parts: &[&[u8]]) -> io::Result<()> {
+    let length: usize = parts.iter().map(|part| part.len()).sum();
+    write!(writer, "{}{}\r\n", token, length)?;
+    for part in parts {
+        writer.write_all(part)?;
+    }
+    writer.write_all(b"\r\n")
+}
+
+fn encode_aggregate<W: Write + ?Sized>(
+    writer: &mut W,
+    token: char,
+    elements: &[RespType],
+) -> io::Result<()> {
+    write!(writer, "{}{}\r\n", token, elements.len())?;
+    for element in elements {
+        element.encode(writer)?;
+    }
+    Ok(())
+}
+
+fn encode_pairs<W: Write + ?Sized>(
+    writer: &mut W,
+    token: char,
+    pairs: &[(RespType, RespType)],
+) -> io::Result<()> {
+    write!(writer, "{}{}\r\n", token, pairs.len())?;
+    for (key, value) in pairs {
+        key.encode(writer)?;
+        value.encode(writer)?;
+    }
+    Ok(())
+}
+
+/// Length of a type token, decimal size and \r\n
+fn header_len(size: usize) -> usize {
+    size.to_string().len() + 3
+}
+
+fn blob_len(size: usize) -> usize {
+    header_len(size) + size + 2
+}
+
+fn aggregate_len(size: usize, elements: &[RespType]) -> usize {
+    let elements: usize = elements.iter().map(RespType::encoded_len).sum();
+    header_len(size) + elements
+}
+
+fn pairs_len(pairs: &[(RespType, RespType)]) -> usize {
+    let elements: usize = pairs
+        .iter()
+        .map(|(key, value)| key.encoded_len() + value.encoded_len())
+        .sum();
+    header_len(pairs.len()) + elements
+}
+
+/// Formats a double the way RESP3 expects, including the inf, -inf and nan forms
+pub(crate) fn format_double(double: f64) -> String {
+    if double.is_nan() {
+        "nan".to_string()
+    } else if double.is_infinite() {
+        match double.is_sign_positive() {
+            true => "inf".to_string(),
+            false => "-inf".to_string(),
+        }
+    } else {
+        double.to_string()
+    }
+}
+
+/// Checks that a string is an optionally signed, non-empty string of digits
+pub(crate) fn is_big_number(string: &str) -> bool {
+    let digits = string.strip_prefix('-').unwrap_or(string);
+    !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
     fn assert_expected_encode(resp: RespType, expected: &str) {
+        let mut encoded = Vec::new();
+        resp.encode(&mut encoded).unwrap();
+        assert_eq!(expected.as_bytes(), encoded);
+        assert_eq!(resp.encoded_len(), expected.len());
+        #[cfg(feature = "bytes")]
+        {
+            let mut buffer = bytes::BytesMut::new();
+            resp.encode_to(&mut buffer);
+            assert_eq!(expected.as_bytes(), buffer);
+        }
+
         let encoded = resp.as_bytes();
         assert_eq!(expected.as_bytes(), encoded);
     }
@@ -198,4 +463,142 @@ mod tests {
         let resp = RespType::NullArray;
         assert_expected_encode(resp, "*-1\r\n");
     }
+
+    #[test]
+    fn map() {
+        let resp = RespType::map(vec![
+            (RespType::SimpleString("a".into()), RespType::Integer(1)),
+            (RespType::Integer(2), RespType::BulkString("b".into())),
+        ]);
+        assert_expected_encode(resp, "%2\r\n+a\r\n:1\r\n:2\r\n$1\r\nb\r\n");
+    }
+
+    #[test]
+    fn empty_map() {
+        let resp = RespType::map(Vec::new());
+        assert_expected_encode(resp, "%0\r\n");
+    }
+
+    #[test]
+    fn set() {
+        let resp = RespType::set(vec![RespType::Integer(1), RespType::Nil]);
+        assert_expected_encode(resp, "~2\r\n:1\r\n_\r\n");
+    }
+
+    #[test]
+    fn push() {
+        let resp = RespType::push(vec![RespType::SimpleString("pong".into())]);
+        assert_expected_encode(resp, ">1\r\n+pong\r\n");
+    }
+
+    #[test]
+    fn is_push() {
+        let push = RespType::push(vec![RespType::SimpleString("pong".into())]);
+        assert!(push.is_push());
+        let attribute = vec![(RespType::SimpleString("key".into()), RespType::Nil)];
+        assert!(RespType::attribute(attribute.clone(), push).is_push());
+        let reply = RespType::attribute(attribute, RespType::Integer(1));
+        assert!(!reply.is_push());
+    }
+
+    #[test]
+    fn attribute() {
+        let resp = RespType::attribute(
+            vec![(
+                RespType::SimpleString("ttl".into()),
+                RespType::Integer(3600),
+            )],
+            RespType::BulkString("value".into()),
+        );
+        assert_expected_encode(resp, "|1\r\n+ttl\r\n:3600\r\n$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn verbatim_string() -> Result<()> {
+        let resp = RespType::verbatim_string("txt".into(), "Some string".into())?;
+        assert_expected_encode(resp, "=15\r\ntxt:Some string\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_verbatim_string() {
+        assert!(RespType::verbatim_string("text".into(), Vec::new()).is_err());
+        assert!(RespType::verbatim_string("t:t".into(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn blob_error() {
+        let resp = RespType::blob_error("SYNTAX invalid syntax".into());
+        assert!(resp.is_error());
+        assert_expected_encode(resp, "!21\r\nSYNTAX invalid syntax\r\n");
+    }
+
+    #[test]
+    fn nil() {
+        let resp = RespType::Nil;
+        assert_expected_encode(resp, "_\r\n");
+    }
+
+    #[test]
+    fn boolean() {
+        assert_expected_encode(RespType::boolean(true), "#t\r\n");
+        assert_expected_encode(RespType::boolean(false), "#f\r\n");
+    }
+
+    #[test]
+    fn double() {
+        assert_expected_encode(RespType::double(1.5), ",1.5\r\n");
+        assert_expected_encode(RespType::double(-10.0), ",-10\r\n");
+    }
+
+    #[test]
+    fn double_special() {
+        assert_expected_encode(RespType::double(f64::INFINITY), ",inf\r\n");
+        assert_expected_encode(RespType::double(f64::NEG_INFINITY), ",-inf\r\n");
+        assert_expected_encode(RespType::double(f64::NAN), ",nan\r\n");
+    }
+
+    #[test]
+    fn big_number() -> Result<()> {
+        let input = "-3492890328409238509324850943850943825024385";
+        let resp = RespType::big_number(input.into())?;
+        assert_expected_encode(resp, "(-3492890328409238509324850943850943825024385\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_big_number() {
+        assert!(RespType::big_number("12a".into()).is_err());
+        assert!(RespType::big_number("-".into()).is_err());
+    }
+
+    #[test]
+    fn encode_nested() {
+        let resp = RespType::Array(vec![
+            RespType::map(vec![(
+                RespType::BulkString("key".into()),
+                RespType::set(vec![RespType::double(-1.25), RespType::Null]),
+            )]),
+            RespType::attribute(
+                vec![(RespType::Nil, RespType::boolean(false))],
+                RespType::Integer(-42),
+            ),
+            RespType::BulkString(vec![b'x'; 1000]),
+        ]);
+        let mut expected = "*3\r\n%1\r\n$3\r\nkey\r\n~2\r\n,-1.25\r\n$-1\r\n".to_string();
+        expected.push_str("|1\r\n_\r\n#f\r\n:-42\r\n$1000\r\n");
+        expected.push_str(&"x".repeat(1000));
+        expected.push_str("\r\n");
+        assert_expected_encode(resp, &expected);
+    }
+
+    #[test]
+    fn encode_reused() {
+        let resp = RespType::command(vec!["GET".into(), "key".into()]);
+        let mut encoded = Vec::new();
+        for _ in 0..3 {
+            resp.encode(&mut encoded).unwrap();
+        }
+        assert_eq!(encoded, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".repeat(3));
+    }
 }
@@ -0,0 +1,451 @@
+use super::buffer::*;
+use super::error::{ParserError, Result};
+use super::parser::{into_pairs, SimpleType};
+use super::RespConfig;
+use super::RespType;
+
+/// Borrowed view of a RESP type, holding slices into the buffer it was parsed
+/// from. Use [`parse_ref`] to decode one, and [`RespRef::to_owned`] to convert
+/// it into a [`RespType`]. See [`RespType`] for details on each variant.
+#[derive(PartialEq, Debug, Clone)]
+pub enum RespRef<'a> {
+    SimpleString(&'a str),
+    Error(&'a str),
+    Integer(i64),
+    BulkString(&'a [u8]),
+    Array(Vec<RespRef<'a>>),
+    Null,
+    NullArray,
+    Map(Vec<(RespRef<'a>, RespRef<'a>)>),
+    Set(Vec<RespRef<'a>>),
+    Push(Vec<RespRef<'a>>),
+    Attribute {
+        attributes: Vec<(RespRef<'a>, RespRef<'a>)>,
+        reply: Box<RespRef<'a>>,
+    },
+    VerbatimString {
+        format: &'a str,
+        text: &'a [u8],
+    },
+    BlobError(&'a [u8]),
+    Nil,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(&'a str),
+    /// A RESP3 streamed bulk string, the chunks are not contiguous in the
+    /// buffer so they are kept separately
+    StreamedString(Vec<&'a [u8]>),
+}
+
+impl<'a> RespRef<'a> {
+    /// Copies the borrowed data into an owned [`RespType`]
+    pub fn to_owned(&self) -> RespType {
+        fn pairs(pairs: &[(RespRef, RespRef)]) -> Vec<(RespType, RespType)> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect()
+        }
+        fn elements(elements: &[RespRef]) -> Vec<RespType> {
+            elements.iter().map(RespRef::to_owned).collect()
+        }
+
+        match self {
+            RespRef::SimpleString(string) => RespType::SimpleString(string.to_string()),
+            RespRef::Error(string) => RespType::Error(string.to_string()),
+            RespRef::Integer(integer) => RespType::Integer(*integer),
+            RespRef::BulkString(string) => RespType::BulkString(string.to_vec()),
+            RespRef::Array(array) => RespType::Array(elements(array)),
+            RespRef::Null => RespType::Null,
+            RespRef::NullArray => RespType::NullArray,
+            RespRef::Map(map) => RespType::Map(pairs(map)),
+            RespRef::Set(set) => RespType::Set(elements(set)),
+            RespRef::Push(push) => RespType::Push(elements(push)),
+            RespRef::Attribute { attributes, reply } => RespType::Attribute {
+                attributes: pairs(attributes),
+                reply: Box::new(RespRef::to_owned(reply)),
+            },
+            RespRef::VerbatimString { format, text } => RespType::VerbatimString {
+                format: format.to_string(),
+                text: text.to_vec(),
+            },
+            RespRef::BlobError(string) => RespType::BlobError(string.to_vec()),
+            RespRef::Nil => RespType::Nil,
+            RespRef::Boolean(boolean) => RespType::Boolean(*boolean),
+            RespRef::Double(double) => RespType::Double(*double),
+            RespRef::BigNumber(string) => RespType::BigNumber(string.to_string()),
+            RespRef::StreamedString(chunks) => RespType::BulkString(chunks.concat()),
+        }
+    }
+}
+
+/// Parses a single RESP type from the start of a buffer without copying,
+/// returns the borrowed result and the number of bytes it used, or `None` if
+/// the buffer does not yet hold a complete RESP type
+pub fn parse_ref(buffer: &[u8]) -> Result<Option<(RespRef<'_>, usize)>> {
+    decode(buffer, 0, &RespConfig::default(), 0)
+}
+
+pub(crate) fn decode<'a>(
+    buffer: &'a [u8],
+    cursor: usize,
+    config: &RespConfig,
+    depth: usize,
+) -> Result<Option<(RespRef<'a>, usize)>> {
+    let token = match buffer.get(cursor) {
+        Some(token) => *token,
+        None => return Ok(None),
+    };
+
+    let start = cursor;
+    if !config.protocol.allows(token) {
+        return Err(ParserError::TypeTokenError(token as char).at(start));
+    }
+    let cursor = cursor + 1;
+    let simple_type = match token {
+        b'+' => SimpleType::String,
+        b'-' => SimpleType::Error,
+        b':' => SimpleType::Integer,
+        b'_' => SimpleType::Null,
+        b'#' => SimpleType::Boolean,
+        b',' => SimpleType::Double,
+        b'(' => SimpleType::BigNumber,
+        b'$' | b'=' | b'!' => return decode_bulk(buffer, cursor, token, config),
+        b'*' | b'%' | b'~' | b'>' | b'|' => {
+            if depth >= config.max_depth {
+                return Err(ParserError::DepthExceededError.at(start));
+            }
+            return decode_aggregate(buffer, cursor, token, config, depth + 1);
+        }
+        other => return Err(ParserError::TypeTokenError(other as char).at(start)),
+    };
+
+    match readline(buffer, cursor, cursor)? {
+        ReadlineResult::Line { line, cursor } => {
+            if line.len() > config.max_resp_size {
+                return Err(ParserError::SizeExceededError.at(start + 1));
+            }
+            Ok(Some((simple_type.build(line, start + 1)?, cursor)))
+        }
+        ReadlineResult::None { .. } => Ok(None),
+    }
+}
+
+fn decode_bulk<'a>(
+    buffer: &'a [u8],
+    cursor: usize,
+    token: u8,
+    config: &RespConfig,
+) -> Result<Option<(RespRef<'a>, usize)>> {
+    let start = cursor;
+    let (cursor, size) = match readsize(buffer, cursor, cursor)? {
+        ReadsizeResult::None(_) => return Ok(None),
+        ReadsizeResult::Null(end) => match token {
+            b'$' => return Ok(Some((RespRef::Null, end))),
+            _ => return Err(ParserError::ReadsizeError(-1).at(start)),
+        },
+        ReadsizeResult::Streamed(end) => match token {
+            b'$' => return decode_streamed_string(buffer, end, config),
+            _ => {
+                let error = format!("{:?} can not be streamed", token as char);
+                return Err(ParserError::ValueError(error).at(start));
+            }
+        },
+        ReadsizeResult::Size { end, size } => (end, size),
+    };
+    if size > config.max_resp_size {
+        return Err(ParserError::SizeExceededError.at(start));
+    }
+
+    let end = cursor + size + 2;
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    let payload = &buffer[cursor..cursor + size];
+    let result = match token {
+        b'!' => RespRef::BlobError(payload),
+        b'=' => {
+            if payload.len() < 4 || payload[3] != b':' {
+                let error = "verbatim string without a format prefix".to_string();
+                return Err(ParserError::ValueError(error).at(cursor));
+            }
+            RespRef::VerbatimString {
+                format: std::str::from_utf8(&payload[..3])
+                    .map_err(|error| ParserError::Utf8Error(error).at(cursor))?,
+                text: &payload[4..],
+            }
+        }
+        _ => RespRef::BulkString(payload),
+    };
+    Ok(Some((result, end)))
+}
+
+fn decode_streamed_string<'a>(
+    buffer: &'a [u8],
+    cursor: usize,
+    config: &RespConfig,
+) -> Result<Option<(RespRef<'a>, usize)>> {
+    let mut chunks = Vec::new();
+    let mut length = 0;
+    let mut start = cursor;
+    loop {
+        match buffer.get(start) {
+            None => return Ok(None),
+            Some(b';') => {}
+            Some(other) => {
+                let error = format!("expected ';' chunk header, got {:?}", *other as char);
+                return Err(ParserError::ValueError(error).at(start));
+            }
+        }
+        let (cursor, size) = match readsize(buffer, start + 1, start + 1)? {
+            ReadsizeResult::None(_) => return Ok(None),
+            ReadsizeResult::Size { end, size: 0 } => {
+                return Ok(Some((RespRef::StreamedString(chunks), end)))
+            }
+            ReadsizeResult::Size { end, size } => (end, size),
+            ReadsizeResult::Null(_) => return Err(ParserError::ReadsizeError(-1).at(start)),
+            ReadsizeResult::Streamed(_) => {
+                let error = "chunk of unknown size".to_string();
+                return Err(ParserError::ValueError(error).at(start));
+            }
+        };
+        length += size;
+        if length > config.max_resp_size {
+            return Err(ParserError::SizeExceededError.at(start));
+        }
+
+        let end = cursor + size + 2;
+        if buffer.len() < end {
+            return Ok(None);
+        }
+        chunks.push(&buffer[cursor..cursor + size]);
+        start = end;
+    }
+}
+
+fn decode_aggregate<'a>(
+    buffer: &'a [u8],
+    cursor: usize,
+    token: u8,
+    config: &RespConfig,
+    depth: usize,
+) -> Result<Option<(RespRef<'a>, usize)>> {
+    let start = cursor;
+    let width = match token {
+        b'%' | b'|' => 2,
+        _ => 1,
+    };
+    let (mut cursor, size) = match readsize(buffer, cursor, cursor)? {
+        ReadsizeResult::None(_) => return Ok(None),
+        ReadsizeResult::Null(end) => match token {
+            b'*' => return Ok(Some((RespRef::NullArray, end))),
+            _ => return Err(ParserError::ReadsizeError(-1).at(start)),
+        },
+        ReadsizeResult::Streamed(end) => match token {
+            b'*' | b'%' | b'~' => (end, None),
+            _ => {
+                let error = format!("{:?} can not be streamed", token as char);
+                return Err(ParserError::ValueError(error).at(start));
+            }
+        },
+        ReadsizeResult::Size { end, size } => {
+            if size > config.max_resp_size / width {
+                return Err(ParserError::SizeExceededError.at(start));
+            }
+            (end, Some(size * width))
+        }
+    };
+
+    let mut elements = Vec::new();
+    loop {
+        match size {
+            Some(size) if elements.len() >= size => break,
+            Some(_) => {}
+            None => match buffer.get(cursor) {
+                None => return Ok(None),
+                Some(b'.') => match readline(buffer, cursor + 1, cursor + 1)? {
+                    ReadlineResult::Line { line, cursor: end } => {
+                        if !line.is_empty() {
+                            let error = format!("end of stream with data {:?}", line);
+                            return Err(ParserError::ValueError(error).at(cursor));
+                        }
+                        if elements.len() % width != 0 {
+                            let error = "streamed map ended without a value".to_string();
+                            return Err(ParserError::ValueError(error).at(cursor));
+                        }
+                        cursor = end;
+                        break;
+                    }
+                    ReadlineResult::None { .. } => return Ok(None),
+                },
+                Some(_) => {
+                    if elements.len() >= config.max_resp_size {
+                        return Err(ParserError::SizeExceededError.at(cursor));
+                    }
+                }
+            },
+        }
+
+        match decode(buffer, cursor, config, depth)? {
+            Some((element, end)) => {
+                elements.push(element);
+                cursor = end;
+            }
+            None => return Ok(None),
+        }
+    }
+
+    let result = match token {
+        b'%' => RespRef::Map(into_pairs(elements)),
+        b'~' => RespRef::Set(elements),
+        b'>' => RespRef::Push(elements),
+        b'|' => {
+            let (reply, end) = match decode(buffer, cursor, config, depth)? {
+                Some(reply) => reply,
+                None => return Ok(None),
+            };
+            let result = match config.discard_attributes {
+                true => reply,
+                false => RespRef::Attribute {
+                    attributes: into_pairs(elements),
+                    reply: Box::new(reply),
+                },
+            };
+            return Ok(Some((result, end)));
+        }
+        _ => RespRef::Array(elements),
+    };
+    Ok(Some((result, cursor)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Protocol;
+    use RespRef::*;
+
+    #[test]
+    fn command() -> Result<()> {
+        let buffer = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n+OK\r\n";
+        let (resp, used) = parse_ref(buffer)?.unwrap();
+
+        assert_eq!(used, 33);
+        match &resp {
+            Array(array) => {
+                assert_eq!(array[0], BulkString(b"SET"));
+                let routed = match array[1] {
+                    BulkString(key) => key.as_ptr(),
+                    _ => panic!("Expected BulkString variant"),
+                };
+                assert_eq!(routed, buffer[17..].as_ptr());
+            }
+            other => panic!("Expected Array variant, got {:#?}", other),
+        }
+
+        let (resp, used) = parse_ref(&buffer[used..])?.unwrap();
+        assert_eq!(resp, SimpleString("OK"));
+        assert_eq!(used, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn incomplete() -> Result<()> {
+        assert_eq!(parse_ref(b"")?, None);
+        assert_eq!(parse_ref(b"*2\r\n$3\r\nGET\r\n")?, None);
+        assert_eq!(parse_ref(b"$5\r\nhel")?, None);
+        assert_eq!(parse_ref(b"+OK\r")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_ref(b"forgot my type!").is_err());
+        assert!(parse_ref(b":hi\r\n").is_err());
+    }
+
+    #[test]
+    fn depth_exceeded() {
+        let config = RespConfig {
+            max_depth: 1,
+            ..Default::default()
+        };
+        assert!(decode(b"*1\r\n:1\r\n", 0, &config, 0).is_ok());
+
+        let error = decode(b"*1\r\n*0\r\n", 0, &config, 0).unwrap_err();
+        assert!(matches!(error.kind(), ParserError::DepthExceededError));
+        assert_eq!(error.offset(), Some(4));
+    }
+
+    #[test]
+    fn attribute_depth_exceeded() {
+        let mut payload = b"|0\r\n".repeat(200_000);
+        payload.extend_from_slice(b"+OK\r\n");
+        let error = parse_ref(&payload).unwrap_err();
+        assert!(matches!(error.kind(), ParserError::DepthExceededError));
+    }
+
+    #[test]
+    fn resp2_only() {
+        let config = RespConfig {
+            protocol: Protocol::Resp2,
+            ..Default::default()
+        };
+        assert!(decode(b"*1\r\n:1\r\n", 0, &config, 0).is_ok());
+        let error = decode(b"*1\r\n#t\r\n", 0, &config, 0).unwrap_err();
+        assert!(matches!(error.kind(), ParserError::TypeTokenError('#')));
+        assert_eq!(error.offset(), Some(4));
+    }
+
+    #[test]
+    fn resp3() -> Result<()> {
+        let buffer = b"%2\r\n=7\r\ntxt:abc\r\n#t\r\n(123\r\n~1\r\n,-inf\r\n";
+        let (resp, _) = parse_ref(buffer)?.unwrap();
+
+        assert_eq!(
+            resp,
+            Map(vec![
+                (
+                    VerbatimString {
+                        format: "txt",
+                        text: b"abc"
+                    },
+                    Boolean(true)
+                ),
+                (BigNumber("123"), Set(vec![Double(f64::NEG_INFINITY)]))
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_string() -> Result<()> {
+        let (resp, used) = parse_ref(b"$?\r\n;3\r\nabc\r\n;2\r\nde\r\n;0\r\n")?.unwrap();
+
+        assert_eq!(used, 25);
+        assert_eq!(resp, StreamedString(vec![b"abc", b"de"]));
+        assert_eq!(resp.to_owned(), RespType::BulkString("abcde".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn to_owned() -> Result<()> {
+        let buffer = b"*4\r\n|1\r\n+ttl\r\n:1\r\n$1\r\na\r\n*-1\r\n$-1\r\n!3\r\nERR\r\n";
+        let (resp, _) = parse_ref(buffer)?.unwrap();
+        let owned = resp.to_owned();
+
+        assert_eq!(
+            owned,
+            RespType::Array(vec![
+                RespType::attribute(
+                    vec![(RespType::SimpleString("ttl".into()), RespType::Integer(1))],
+                    RespType::BulkString("a".into())
+                ),
+                RespType::NullArray,
+                RespType::Null,
+                RespType::BlobError("ERR".into()),
+            ])
+        );
+        assert_eq!(owned.as_bytes(), buffer);
+        Ok(())
+    }
+}
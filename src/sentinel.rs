@@ -0,0 +1,224 @@
+use super::error::{ClientError, ClientResult};
+use super::Connection;
+use super::ConnectionConfig;
+use super::RespType;
+
+/// Settings used by a [`Sentinel`]
+#[derive(Clone, Debug, Default)]
+pub struct SentinelConfig {
+    /// Settings for the connections to the sentinels
+    pub sentinel: ConnectionConfig,
+    /// Settings for the connection to the master
+    pub master: ConnectionConfig,
+}
+
+/// Finds the current master of a service through Redis Sentinel.
+///
+/// Sentinels are asked in order, an unreachable sentinel or one that does not
+/// know the master is skipped. The sentinel that answered is moved to the
+/// front so it is asked first next time.
+pub struct Sentinel {
+    sentinels: Vec<String>,
+    config: SentinelConfig,
+}
+
+impl Sentinel {
+    /// Creates a client for the given sentinel addresses, no connection is
+    /// made until the master is looked up
+    pub fn new<S: AsRef<str>>(sentinels: &[S], config: &SentinelConfig) -> Self {
+        Sentinel {
+            sentinels: sentinels
+                .iter()
+                .map(|sentinel| sentinel.as_ref().to_string())
+                .collect(),
+            config: config.clone(),
+        }
+    }
+
+    /// The sentinel addresses, in the order they will be asked
+    pub fn sentinels(&self) -> &[String] {
+        &self.sentinels
+    }
+
+    /// Asks the sentinels for the address of the master, as `host:port`
+    pub fn master_addr(&mut self, name: &str) -> ClientResult<String> {
+        self.find(name, |addr, _| Ok(addr))
+    }
+
+    /// Connects to the master, checking with `ROLE` that it still is one. If
+    /// a sentinel points at a node that is not a master, the next sentinel is
+    /// asked.
+    pub fn connect_master(&mut self, name: &str) -> ClientResult<Connection> {
+        self.find(name, |addr, config| {
+            let mut connection = Connection::connect_tcp(addr.as_str(), &config.master)?;
+            match connection.command(vec!["ROLE".into()])? {
+                RespType::Array(role)
+                    if role.first().and_then(RespType::as_text) == Some("master") =>
+                {
+                    Ok(connection)
+                }
+                other => Err(ClientError::UnexpectedReplyError(other)),
+            }
+        })
+    }
+
+    /// Tries each sentinel in turn until `found` succeeds for the address it
+    /// gives, returning the last error if none do
+    fn find<T, F>(&mut self, name: &str, mut found: F) -> ClientResult<T>
+    where
+        F: FnMut(String, &SentinelConfig) -> ClientResult<T>,
+    {
+        let mut last_error = ClientError::MasterNotFoundError(name.to_string());
+        for index in 0..self.sentinels.len() {
+            let result = self
+                .query(&self.sentinels[index], name)
+                .and_then(|addr| found(addr, &self.config));
+            match result {
+                Ok(result) => {
+                    self.sentinels[..=index].rotate_right(1);
+                    return Ok(result);
+                }
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn query(&self, sentinel: &str, name: &str) -> ClientResult<String> {
+        let mut connection = Connection::connect_tcp(sentinel, &self.config.sentinel)?;
+        let reply = connection.command(vec![
+            "SENTINEL".into(),
+            "get-master-addr-by-name".into(),
+            name.into(),
+        ])?;
+        match reply {
+            RespType::NullArray | RespType::Null | RespType::Nil => {
+                Err(ClientError::MasterNotFoundError(name.to_string()))
+            }
+            reply => match parse_addr(&reply) {
+                Some(addr) => Ok(addr),
+                None => Err(ClientError::UnexpectedReplyError(reply)),
+            },
+        }
+    }
+}
+
+/// Parses the `[host, port]` reply into `host:port`
+fn parse_addr(reply: &RespType) -> Option<String> {
+    match reply {
+        RespType::Array(addr) if addr.len() == 2 => {
+            let host = addr[0].as_text()?;
+            let port: u16 = addr[1].as_text()?.parse().ok()?;
+            match host.contains(':') {
+                true => Some(format!("[{}]:{}", host, port)),
+                false => Some(format!("{}:{}", host, port)),
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::serve;
+    use std::net::{SocketAddr, TcpListener};
+    use RespType::*;
+
+    fn bulk(data: &str) -> RespType {
+        BulkString(data.into())
+    }
+
+    fn sentinel(master: SocketAddr) -> SocketAddr {
+        serve(move |command| match command[1].as_slice() {
+            b"get-master-addr-by-name" if command[2] == b"mymaster" => Array(vec![
+                bulk(&master.ip().to_string()),
+                bulk(&master.port().to_string()),
+            ]),
+            _ => NullArray,
+        })
+    }
+
+    fn node(role: &'static str) -> SocketAddr {
+        serve(move |command| match command[0].as_slice() {
+            b"ROLE" => Array(vec![bulk(role), Integer(0), Array(vec![])]),
+            _ => bulk(role),
+        })
+    }
+
+    /// An address nothing is listening on
+    fn unreachable() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let reply = Array(vec![bulk("10.0.0.1"), bulk("6379")]);
+        assert_eq!(parse_addr(&reply), Some("10.0.0.1:6379".into()));
+        let reply = Array(vec![bulk("::1"), bulk("6379")]);
+        assert_eq!(parse_addr(&reply), Some("[::1]:6379".into()));
+        assert_eq!(parse_addr(&Array(vec![bulk("10.0.0.1")])), None);
+        assert_eq!(parse_addr(&Array(vec![bulk("a"), bulk("port")])), None);
+    }
+
+    #[test]
+    fn master_addr() -> ClientResult<()> {
+        let master = node("master");
+        let mut sentinel =
+            Sentinel::new(&[sentinel(master).to_string()], &SentinelConfig::default());
+        assert_eq!(sentinel.master_addr("mymaster")?, master.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn connect_master() -> ClientResult<()> {
+        let master = node("master");
+        let mut connection =
+            Sentinel::new(&[sentinel(master).to_string()], &SentinelConfig::default())
+                .connect_master("mymaster")?;
+        assert_eq!(connection.command(vec!["GET".into()])?, bulk("master"));
+        Ok(())
+    }
+
+    #[test]
+    fn failover() -> ClientResult<()> {
+        let down = unreachable().to_string();
+        let up = sentinel(node("master")).to_string();
+        let mut sentinel = Sentinel::new(&[&down, &up], &SentinelConfig::default());
+        sentinel.connect_master("mymaster")?;
+        // The sentinel that answered is asked first from now on
+        assert_eq!(sentinel.sentinels(), [up, down]);
+        Ok(())
+    }
+
+    #[test]
+    fn not_master() -> ClientResult<()> {
+        let stale = sentinel(node("slave")).to_string();
+        let current = sentinel(node("master")).to_string();
+        let mut connection = Sentinel::new(&[stale, current], &SentinelConfig::default())
+            .connect_master("mymaster")?;
+        assert_eq!(connection.command(vec!["GET".into()])?, bulk("master"));
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_master() {
+        let mut sentinel = Sentinel::new(
+            &[sentinel(node("master")).to_string()],
+            &SentinelConfig::default(),
+        );
+        match sentinel.master_addr("other") {
+            Err(ClientError::MasterNotFoundError(name)) => assert_eq!(name, "other"),
+            other => panic!("expected the master to not be found, got {:?}", other),
+        }
+
+        let mut sentinel = Sentinel::new(&[unreachable().to_string()], &SentinelConfig::default());
+        assert!(matches!(
+            sentinel.master_addr("mymaster"),
+            Err(ClientError::IoError(_))
+        ));
+    }
+}
@@ -0,0 +1,589 @@
+//! A small framework for RESP speaking servers. A [`Handler`] replies to
+//! each command, and a [`Server`] runs the accept loop with a thread per
+//! connection, decoding pipelined commands with a [`RespParser`]. Replies and
+//! other frames are buffered per connection and written by a thread of its
+//! own, so a client that stops reading never blocks the handler.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use super::connection::Stream;
+use super::RespConfig;
+use super::RespParser;
+use super::RespType;
+
+const READ_SIZE: usize = 16 * 1024;
+
+/// Default limit on the bytes buffered for a client, the same as the hard
+/// limit redis-server sets for pub/sub clients
+pub const DEFAULT_OUTPUT_LIMIT: usize = 32 * 1024 * 1024;
+
+/// Replies to the commands sent to a [`Server`]. One handler is shared by
+/// all connections, and called for one command at a time.
+pub trait Handler: Send + 'static {
+    /// Handles a command, given as its arguments with the command name
+    /// first, and returns the reply
+    fn call(&mut self, ctx: &mut Context, command: Vec<Vec<u8>>) -> RespType;
+
+    /// Called once a connection has closed, to clean up any state kept for
+    /// it
+    fn disconnected(&mut self, _ctx: &Context) {}
+}
+
+impl<F> Handler for F
+where
+    F: FnMut(&mut Context, Vec<Vec<u8>>) -> RespType + Send + 'static,
+{
+    fn call(&mut self, ctx: &mut Context, command: Vec<Vec<u8>>) -> RespType {
+        self(ctx, command)
+    }
+}
+
+/// The connection a command was sent on
+pub struct Context {
+    id: u64,
+    peer_addr: Option<SocketAddr>,
+    writer: Writer,
+    closing: bool,
+}
+
+impl Context {
+    /// Unique ID of the connection, starting at 1
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Address of the client, `None` for Unix domain sockets
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// A handle for sending frames to the client outside of replies, such as
+    /// pub/sub messages
+    pub fn writer(&self) -> Writer {
+        self.writer.clone()
+    }
+
+    /// Closes the connection once the reply to the current command is written
+    pub fn close(&mut self) {
+        self.closing = true;
+    }
+}
+
+/// Sends frames to a client, can be kept and used from any thread while the
+/// connection is open
+#[derive(Clone)]
+pub struct Writer {
+    shared: Arc<Shared>,
+}
+
+/// The output of a connection, shared between its writers and the thread
+/// writing to the stream
+struct Shared {
+    output: Mutex<Output>,
+    /// Signalled when there is output to write or the connection is closed
+    ready: Condvar,
+    /// Only used to shut the connection down when a client falls behind
+    stream: Stream,
+    limit: usize,
+}
+
+/// The encoded frames waiting to be written
+struct Output {
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+impl Writer {
+    /// Queues a frame for the client, after any replies still waiting to be
+    /// written so the client sees them in order. Never waits for the client
+    /// to read, instead the connection is closed once more than the output
+    /// limit is waiting.
+    pub fn send(&self, resp: &RespType) -> io::Result<()> {
+        self.queue(resp)?;
+        self.flush()
+    }
+
+    fn queue(&self, resp: &RespType) -> io::Result<()> {
+        let mut output = self.lock();
+        if output.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        resp.encode(&mut output.pending)?;
+        if output.pending.len() > self.shared.limit {
+            // Dropping the output of a client that does not read, the
+            // shutdown also wakes up its reading and writing threads
+            output.closed = true;
+            output.pending = Vec::new();
+            let _ = self.shared.stream.shutdown();
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        Ok(())
+    }
+
+    /// Wakes up the writing thread for the queued frames
+    fn flush(&self) -> io::Result<()> {
+        if self.lock().closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+
+    /// Closes the connection once the queued frames are written
+    fn close(&self) {
+        self.lock().closed = true;
+        self.shared.ready.notify_one();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Output> {
+        self.shared
+            .output
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Accepts connections and answers their commands with a [`Handler`]
+pub struct Server<H> {
+    handler: Arc<Mutex<H>>,
+    config: RespConfig,
+    next_id: Arc<AtomicU64>,
+    output_limit: usize,
+}
+
+impl<H: Handler> Server<H> {
+    /// Creates a server with the default parser limits
+    pub fn new(handler: H) -> Self {
+        Self::with_config(handler, RespConfig::default())
+    }
+
+    /// Creates a server, each connection gets a parser with this config
+    pub fn with_config(handler: H, config: RespConfig) -> Self {
+        Server {
+            handler: Arc::new(Mutex::new(handler)),
+            config,
+            next_id: Arc::new(AtomicU64::new(1)),
+            output_limit: DEFAULT_OUTPUT_LIMIT,
+        }
+    }
+
+    /// Sets how many bytes may wait to be written to a client before its
+    /// connection is closed, [`DEFAULT_OUTPUT_LIMIT`] by default
+    pub fn set_output_limit(&mut self, limit: usize) {
+        self.output_limit = limit;
+    }
+
+    /// Locks the handler, for looking at its state from outside of a command
+    pub fn handler(&self) -> MutexGuard<'_, H> {
+        lock(&self.handler)
+    }
+
+    /// Serves connections accepted on a TCP listener, each on its own thread.
+    /// Only returns if accepting fails.
+    pub fn serve_tcp(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            let peer_addr = stream.peer_addr().ok();
+            self.spawn(Stream::Tcp(stream), peer_addr)?;
+        }
+        Ok(())
+    }
+
+    /// Serves connections accepted on a Unix domain socket listener, each on
+    /// its own thread. Only returns if accepting fails.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: UnixListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            self.spawn(Stream::Unix(stream?), None)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spawn(&self, stream: Stream, peer_addr: Option<SocketAddr>) -> io::Result<()> {
+        let writer = Writer {
+            shared: Arc::new(Shared {
+                output: Mutex::new(Output {
+                    pending: Vec::new(),
+                    closed: false,
+                }),
+                ready: Condvar::new(),
+                stream: stream.try_clone()?,
+                limit: self.output_limit,
+            }),
+        };
+        let ctx = Context {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            writer: writer.clone(),
+            closing: false,
+        };
+        let output = stream.try_clone()?;
+        let handler = self.handler.clone();
+        let parser = RespParser::new(self.config.clone());
+        thread::spawn(move || write_output(output, writer));
+        thread::spawn(move || serve_connection(stream, ctx, handler, parser));
+        Ok(())
+    }
+}
+
+fn lock<H>(handler: &Mutex<H>) -> MutexGuard<'_, H> {
+    // A handler that panicked on another connection is still usable
+    handler.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reads commands until the client disconnects, the replies to all commands
+/// in one read are queued together
+fn serve_connection<H: Handler>(
+    mut stream: Stream,
+    mut ctx: Context,
+    handler: Arc<Mutex<H>>,
+    mut parser: RespParser,
+) {
+    let mut buffer = vec![0; READ_SIZE];
+    while !ctx.closing {
+        let read = match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        // Commands are taken one at a time, so the ones before a protocol
+        // error or a closing command are still answered
+        let mut input = &buffer[..read];
+        let mut error = None;
+        while !ctx.closing {
+            let command = match parser.read_limited(input, 1) {
+                Ok(mut commands) => match commands.pop() {
+                    Some(command) => command,
+                    None => break,
+                },
+                Err(parse_error) => {
+                    error = Some(parse_error.to_string());
+                    break;
+                }
+            };
+            input = &[];
+            let command = match into_command(command) {
+                Some(command) if command.is_empty() => continue,
+                Some(command) => command,
+                None => {
+                    error = Some("expected an array of bulk strings".into());
+                    break;
+                }
+            };
+            let mut handler = lock(&handler);
+            let reply = handler.call(&mut ctx, command);
+            // Queued before the handler is unlocked, so frames sent to this
+            // client while handling other connections come after it
+            if ctx.writer.queue(&reply).is_err() {
+                ctx.closing = true;
+            }
+        }
+        // Replies to the commands before a protocol error are still sent
+        if let Some(error) = error {
+            let _ = ctx
+                .writer
+                .queue(&RespType::Error(format!("ERR Protocol error: {}", error)));
+            ctx.closing = true;
+        }
+
+        if ctx.writer.flush().is_err() {
+            break;
+        }
+    }
+    // The writing thread shuts the connection down once the last replies
+    // are written
+    ctx.writer.close();
+    lock(&handler).disconnected(&ctx);
+}
+
+/// Writes the output of a connection as it is queued, until the connection
+/// is closed or the client stops accepting data
+fn write_output(mut stream: Stream, writer: Writer) {
+    loop {
+        let mut output = writer.lock();
+        while output.pending.is_empty() && !output.closed {
+            output = writer
+                .shared
+                .ready
+                .wait(output)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if output.pending.is_empty() {
+            break;
+        }
+        let pending = std::mem::take(&mut output.pending);
+        drop(output);
+        if stream.write_all(&pending).is_err() {
+            writer.lock().closed = true;
+            break;
+        }
+    }
+    let _ = stream.shutdown();
+}
+
+fn into_command(resp: RespType) -> Option<Vec<Vec<u8>>> {
+    match resp {
+        RespType::Array(args) => args
+            .into_iter()
+            .map(|arg| match arg {
+                RespType::BulkString(arg) => Some(arg),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientError, ClientResult, Connection, ConnectionConfig, Pipeline};
+    use std::collections::HashMap;
+    use std::net::TcpStream;
+    use RespType::*;
+
+    /// Starts a server on an ephemeral port
+    fn start<H: Handler>(handler: H) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || Server::new(handler).serve_tcp(listener));
+        addr
+    }
+
+    fn connect(addr: SocketAddr) -> ClientResult<Connection> {
+        Connection::connect_tcp(addr, &ConnectionConfig::default())
+    }
+
+    /// Keeps a counter per key, and the writers of connections waiting for
+    /// published messages
+    #[derive(Default)]
+    struct Counter {
+        counts: HashMap<Vec<u8>, i64>,
+        subscribers: HashMap<u64, Writer>,
+        disconnected: Vec<u64>,
+    }
+
+    impl Handler for Counter {
+        fn call(&mut self, ctx: &mut Context, command: Vec<Vec<u8>>) -> RespType {
+            match command[0].as_slice() {
+                b"INCR" => {
+                    let count = self.counts.entry(command[1].clone()).or_default();
+                    *count += 1;
+                    Integer(*count)
+                }
+                b"ID" => Integer(ctx.id() as i64),
+                b"SUBSCRIBE" => {
+                    self.subscribers.insert(ctx.id(), ctx.writer());
+                    SimpleString("OK".into())
+                }
+                b"PUBLISH" => {
+                    let message = Push(vec![BulkString(command[1].clone())]);
+                    for writer in self.subscribers.values() {
+                        let _ = writer.send(&message);
+                    }
+                    Integer(self.subscribers.len() as i64)
+                }
+                b"QUIT" => {
+                    ctx.close();
+                    SimpleString("OK".into())
+                }
+                _ => Error("ERR unknown command".into()),
+            }
+        }
+
+        fn disconnected(&mut self, ctx: &Context) {
+            self.subscribers.remove(&ctx.id());
+            self.disconnected.push(ctx.id());
+        }
+    }
+
+    fn raw_exchange(addr: SocketAddr, request: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(request)?;
+        // The server closes the connection after a protocol error
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn commands() -> ClientResult<()> {
+        let addr = start(Counter::default());
+        let mut connection = connect(addr)?;
+        assert_eq!(
+            connection.command(vec!["INCR".into(), "a".into()])?,
+            Integer(1)
+        );
+        assert_eq!(
+            connection.command(vec!["INCR".into(), "a".into()])?,
+            Integer(2)
+        );
+        assert!(matches!(
+            connection.command(vec!["NOPE".into()]),
+            Err(ClientError::ReplyError(_))
+        ));
+
+        // State is shared between connections, IDs are not
+        let mut other = connect(addr)?;
+        assert_eq!(other.command(vec!["INCR".into(), "a".into()])?, Integer(3));
+        let ids = (
+            connection.command(vec!["ID".into()])?,
+            other.command(vec!["ID".into()])?,
+        );
+        assert_ne!(ids.0, ids.1);
+        Ok(())
+    }
+
+    #[test]
+    fn pipelined() -> ClientResult<()> {
+        let addr = start(Counter::default());
+        let mut connection = connect(addr)?;
+        let mut pipeline = Pipeline::new();
+        for _ in 0..100 {
+            pipeline.command(vec!["INCR".into(), "a".into()]);
+        }
+        let results = connection.pipeline(&pipeline)?;
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result?, Integer(i as i64 + 1));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn closure() -> ClientResult<()> {
+        let addr = start(|_: &mut Context, command: Vec<Vec<u8>>| BulkString(command.concat()));
+        let mut connection = connect(addr)?;
+        assert_eq!(
+            connection.command(vec!["a".into(), "b".into()])?,
+            BulkString("ab".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn protocol_error() -> io::Result<()> {
+        let addr = start(Counter::default());
+        let response = raw_exchange(
+            addr,
+            b"*2\r\n$4\r\nINCR\r\n$1\r\na\r\nx\r\n*1\r\n$2\r\nID\r\n",
+        )?;
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with(":1\r\n-ERR Protocol error"));
+        assert!(response.ends_with("\r\n"));
+
+        let response = raw_exchange(addr, b"*1\r\n:1\r\n")?;
+        assert_eq!(
+            response,
+            b"-ERR Protocol error: expected an array of bulk strings\r\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn inline() -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let config = RespConfig {
+            inline_commands: true,
+            ..Default::default()
+        };
+        let server = Server::with_config(Counter::default(), config);
+        thread::spawn(move || server.serve_tcp(listener));
+
+        let response = raw_exchange(addr, b"INCR \"a b\"\r\nINCR 'a b'\r\nQUIT\r\n")?;
+        assert_eq!(response, b":1\r\n:2\r\n+OK\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn close() -> io::Result<()> {
+        let addr = start(Counter::default());
+        let response = raw_exchange(addr, b"*1\r\n$4\r\nQUIT\r\n*1\r\n$2\r\nID\r\n")?;
+        assert_eq!(response, b"+OK\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn writer() -> ClientResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = Arc::new(Server::new(Counter::default()));
+        let serving = server.clone();
+        thread::spawn(move || serving.serve_tcp(listener));
+
+        let mut subscriber = connect(addr)?;
+        subscriber.command(vec!["SUBSCRIBE".into()])?;
+        let mut publisher = connect(addr)?;
+        assert_eq!(
+            publisher.command(vec!["PUBLISH".into(), "hi".into()])?,
+            Integer(1)
+        );
+        assert_eq!(subscriber.receive()?, Push(vec![BulkString("hi".into())]));
+
+        drop(subscriber);
+        // The subscriber is cleaned up once its connection is closed
+        for _ in 0..500 {
+            if !server.handler().disconnected.is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(server.handler().subscribers.is_empty());
+        assert_eq!(server.handler().disconnected.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn output_limit() -> ClientResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let mut server = Server::new(Counter::default());
+        server.set_output_limit(64 * 1024);
+        let server = Arc::new(server);
+        let serving = server.clone();
+        thread::spawn(move || serving.serve_tcp(listener));
+
+        // A subscriber that stops reading does not hold up the publisher,
+        // it is disconnected once too much output is waiting for it
+        let mut subscriber = connect(addr)?;
+        subscriber.command(vec!["SUBSCRIBE".into()])?;
+        let mut publisher = connect(addr)?;
+        let message = vec![b'x'; 1024];
+        for _ in 0..4096 {
+            publisher.command(vec!["PUBLISH".into(), message.clone()])?;
+        }
+        assert_eq!(
+            publisher.command(vec!["PUBLISH".into(), message])?,
+            Integer(0)
+        );
+        assert!(server.handler().subscribers.is_empty());
+        // What was written before the limit was hit is still delivered
+        while let Ok(reply) = subscriber.receive() {
+            assert_eq!(reply, Push(vec![BulkString(vec![b'x'; 1024])]));
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() -> ClientResult<()> {
+        let path = std::env::temp_dir().join(format!("kresp-server-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        thread::spawn(move || Server::new(Counter::default()).serve_unix(listener));
+
+        let mut connection = Connection::connect_unix(&path, &ConnectionConfig::default())?;
+        assert_eq!(
+            connection.command(vec!["INCR".into(), "a".into()])?,
+            Integer(1)
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
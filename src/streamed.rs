@@ -0,0 +1,104 @@
+use bstr::ByteVec;
+
+/// RESP3 types that can be encoded in the streamed form, for producers that
+/// don't know the length of a value in advance.
+///
+/// A streamed value is encoded as [`StreamedType::start`], followed by
+/// [`StreamedType::chunk`] for each piece of a string, or by the encoded
+/// elements of an aggregate (see [`RespType::as_bytes`](crate::RespType::as_bytes)),
+/// and finished with [`StreamedType::end`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamedType {
+    /// Streamed bulk string, sent as chunks
+    BulkString,
+    /// Streamed array
+    Array,
+    /// Streamed map, elements are sent as alternating keys and values
+    Map,
+    /// Streamed set
+    Set,
+}
+
+impl StreamedType {
+    /// Encodes the header that starts a streamed value
+    pub fn start(&self) -> Vec<u8> {
+        let header: &[u8] = match self {
+            StreamedType::BulkString => b"$?\r\n",
+            StreamedType::Array => b"*?\r\n",
+            StreamedType::Map => b"%?\r\n",
+            StreamedType::Set => b"~?\r\n",
+        };
+        header.to_vec()
+    }
+
+    /// Encodes a chunk of a streamed bulk string. An empty chunk would end the
+    /// string, so nothing is encoded for empty data.
+    pub fn chunk(data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if !data.is_empty() {
+            bytes.push_char(';');
+            bytes.push_str(data.len().to_string());
+            bytes.push_str("\r\n");
+            bytes.push_str(data);
+            bytes.push_str("\r\n");
+        }
+        bytes
+    }
+
+    /// Encodes the terminator that ends a streamed value
+    pub fn end(&self) -> Vec<u8> {
+        let end: &[u8] = match self {
+            StreamedType::BulkString => b";0\r\n",
+            _ => b".\r\n",
+        };
+        end.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Result;
+    use crate::{RespParser, RespType};
+
+    #[test]
+    fn bulk_string() -> Result<()> {
+        let mut bytes = StreamedType::BulkString.start();
+        bytes.extend(StreamedType::chunk(b"Hello "));
+        bytes.extend(StreamedType::chunk(b""));
+        bytes.extend(StreamedType::chunk(b"world"));
+        bytes.extend(StreamedType::BulkString.end());
+        assert_eq!(bytes, b"$?\r\n;6\r\nHello \r\n;5\r\nworld\r\n;0\r\n");
+
+        let results = RespParser::default().read(&bytes)?;
+        assert_eq!(results, vec![RespType::BulkString("Hello world".into())]);
+        Ok(())
+    }
+
+    #[test]
+    fn map() -> Result<()> {
+        let mut bytes = StreamedType::Map.start();
+        bytes.extend(RespType::SimpleString("key".into()).as_bytes());
+        bytes.extend(RespType::Integer(1).as_bytes());
+        bytes.extend(StreamedType::Map.end());
+        assert_eq!(bytes, b"%?\r\n+key\r\n:1\r\n.\r\n");
+
+        let results = RespParser::default().read(&bytes)?;
+        assert_eq!(
+            results,
+            vec![RespType::Map(vec![(
+                RespType::SimpleString("key".into()),
+                RespType::Integer(1)
+            )])]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn array_and_set() {
+        assert_eq!(StreamedType::Array.start(), b"*?\r\n");
+        assert_eq!(StreamedType::Set.start(), b"~?\r\n");
+        assert_eq!(StreamedType::Array.end(), b".\r\n");
+        assert_eq!(StreamedType::Set.end(), b".\r\n");
+    }
+}
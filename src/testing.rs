@@ -0,0 +1,1325 @@
+//! An in-memory Redis stand-in for integration tests. [`FakeRedis`] listens
+//! on an ephemeral local port and speaks RESP2 and RESP3 through this crate's
+//! own parser and encoder, covering a core subset of commands:
+//!
+//! - connection: `PING`, `ECHO`, `HELLO`, `AUTH`, `SELECT`, `CLIENT ID`,
+//!   `CLIENT SETNAME`, `CLIENT GETNAME`, `QUIT`
+//! - keys: `DEL`, `EXISTS`, `EXPIRE`, `PEXPIRE`, `TTL`, `PTTL`, `PERSIST`,
+//!   `TYPE`, `DBSIZE`, `FLUSHDB`, `FLUSHALL`
+//! - strings: `GET`, `SET`, `MGET`, `MSET`, `INCR`, `INCRBY`, `DECR`,
+//!   `DECRBY`, `APPEND`, `STRLEN`
+//! - hashes: `HSET`, `HGET`, `HMGET`, `HDEL`, `HGETALL`, `HEXISTS`, `HLEN`,
+//!   `HKEYS`, `HVALS`, `HINCRBY`
+//! - lists: `LPUSH`, `RPUSH`, `LPOP`, `RPOP`, `LLEN`, `LRANGE`, `LINDEX`
+//! - sets: `SADD`, `SREM`, `SMEMBERS`, `SISMEMBER`, `SCARD`
+//! - transactions: `MULTI`, `EXEC`, `DISCARD`
+//! - pub/sub: `SUBSCRIBE`, `UNSUBSCRIBE`, `PUBLISH`
+//!
+//! There is a single database, passwords are not checked and keys expire
+//! lazily when next accessed.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::connection::Stream;
+use super::error::ClientResult;
+use super::server::{Context, Handler, Server, Writer};
+use super::Connection;
+use super::ConnectionConfig;
+use super::Protocol;
+use super::RespConfig;
+use super::RespType;
+
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+const NOT_INTEGER: &str = "ERR value is not an integer or out of range";
+
+/// Commands and their arity, counting the command name. A negative arity is
+/// a minimum.
+const COMMANDS: &[(&str, i32)] = &[
+    ("PING", -1),
+    ("ECHO", 2),
+    ("HELLO", -1),
+    ("AUTH", -2),
+    ("SELECT", 2),
+    ("CLIENT", -2),
+    ("QUIT", -1),
+    ("DEL", -2),
+    ("EXISTS", -2),
+    ("EXPIRE", 3),
+    ("PEXPIRE", 3),
+    ("TTL", 2),
+    ("PTTL", 2),
+    ("PERSIST", 2),
+    ("TYPE", 2),
+    ("DBSIZE", 1),
+    ("FLUSHDB", -1),
+    ("FLUSHALL", -1),
+    ("GET", 2),
+    ("SET", -3),
+    ("MGET", -2),
+    ("MSET", -3),
+    ("INCR", 2),
+    ("INCRBY", 3),
+    ("DECR", 2),
+    ("DECRBY", 3),
+    ("APPEND", 3),
+    ("STRLEN", 2),
+    ("HSET", -4),
+    ("HGET", 3),
+    ("HMGET", -3),
+    ("HDEL", -3),
+    ("HGETALL", 2),
+    ("HEXISTS", 3),
+    ("HLEN", 2),
+    ("HKEYS", 2),
+    ("HVALS", 2),
+    ("HINCRBY", 4),
+    ("LPUSH", -3),
+    ("RPUSH", -3),
+    ("LPOP", -2),
+    ("RPOP", -2),
+    ("LLEN", 2),
+    ("LRANGE", 4),
+    ("LINDEX", 3),
+    ("SADD", -3),
+    ("SREM", -3),
+    ("SMEMBERS", 2),
+    ("SISMEMBER", 3),
+    ("SCARD", 2),
+    ("MULTI", 1),
+    ("EXEC", 1),
+    ("DISCARD", 1),
+    ("SUBSCRIBE", -2),
+    ("UNSUBSCRIBE", -1),
+    ("PUBLISH", 3),
+];
+
+/// A reply, or an error reply to send instead
+type Reply = Result<RespType, RespType>;
+
+/// An in-memory Redis-compatible server running on its own threads, stopped
+/// when dropped. Connections that are still open keep being served until
+/// the client closes them.
+pub struct FakeRedis {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl FakeRedis {
+    /// Starts a server on an ephemeral port of `127.0.0.1`
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let config = RespConfig {
+            inline_commands: true,
+            ..Default::default()
+        };
+        let server = Server::with_config(Store::default(), config);
+
+        let accepting = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let _ = stream.set_nodelay(true);
+                let peer_addr = stream.peer_addr().ok();
+                let _ = server.spawn(Stream::Tcp(stream), peer_addr);
+            }
+        });
+        Ok(FakeRedis { addr, stopped })
+    }
+
+    /// The address the server listens on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Opens a client connection to the server
+    pub fn connect(&self, config: &ConnectionConfig) -> ClientResult<Connection> {
+        Connection::connect_tcp(self.addr, config)
+    }
+}
+
+impl Drop for FakeRedis {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes up the accept loop so it sees the server was stopped
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    keys: HashMap<Vec<u8>, Entry>,
+    clients: HashMap<u64, Client>,
+}
+
+struct Entry {
+    value: Value,
+    expires: Option<Instant>,
+}
+
+/// Hashes and sets keep insertion order, like small ones do in Redis
+enum Value {
+    String(Vec<u8>),
+    Hash(Vec<(Vec<u8>, Vec<u8>)>),
+    List(VecDeque<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+}
+
+/// State kept for each connection
+struct Client {
+    protocol: Protocol,
+    name: Option<Vec<u8>>,
+    /// Commands queued since `MULTI`
+    queued: Option<Vec<Vec<Vec<u8>>>>,
+    /// Whether a command failed to queue, so `EXEC` must fail
+    aborted: bool,
+    channels: Vec<Vec<u8>>,
+    writer: Writer,
+}
+
+impl Entry {
+    fn expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires <= Instant::now())
+    }
+}
+
+impl Value {
+    fn as_string(&mut self) -> Option<&mut Vec<u8>> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn as_hash(&mut self) -> Option<&mut Vec<(Vec<u8>, Vec<u8>)>> {
+        match self {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn as_list(&mut self) -> Option<&mut VecDeque<Vec<u8>>> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn as_set(&mut self) -> Option<&mut Vec<Vec<u8>>> {
+        match self {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::Hash(hash) => hash.is_empty(),
+            Value::List(list) => list.is_empty(),
+            Value::Set(set) => set.is_empty(),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Hash(_) => "hash",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+        }
+    }
+}
+
+impl Handler for Store {
+    fn call(&mut self, ctx: &mut Context, mut command: Vec<Vec<u8>>) -> RespType {
+        let id = ctx.id();
+        let client = self.clients.entry(id).or_insert_with(|| Client {
+            protocol: Protocol::Resp2,
+            name: None,
+            queued: None,
+            aborted: false,
+            channels: Vec::new(),
+            writer: ctx.writer(),
+        });
+        command[0].make_ascii_uppercase();
+        let name = command[0].clone();
+
+        if let Err(error) = check_arity(&command) {
+            if client.queued.is_some() {
+                client.aborted = true;
+            }
+            return error;
+        }
+        let name = name.as_slice();
+        if client.protocol == Protocol::Resp2
+            && !client.channels.is_empty()
+            && !matches!(name, b"SUBSCRIBE" | b"UNSUBSCRIBE" | b"PING" | b"QUIT")
+        {
+            return error(format!(
+                "ERR Can't execute '{}': only SUBSCRIBE / UNSUBSCRIBE / PING / QUIT \
+                 are allowed in this context",
+                lowercase(name)
+            ));
+        }
+
+        match (name, client.queued.as_mut()) {
+            (b"MULTI", Some(_)) => error("ERR MULTI calls can not be nested".into()),
+            (b"MULTI", None) => {
+                client.queued = Some(Vec::new());
+                client.aborted = false;
+                ok()
+            }
+            (b"EXEC", None) => error("ERR EXEC without MULTI".into()),
+            (b"DISCARD", None) => error("ERR DISCARD without MULTI".into()),
+            (b"DISCARD", Some(_)) => {
+                client.queued = None;
+                ok()
+            }
+            (b"EXEC", Some(_)) => {
+                let queued = client.queued.take().unwrap_or_default();
+                if client.aborted {
+                    return error(
+                        "EXECABORT Transaction discarded because of previous errors.".into(),
+                    );
+                }
+                let replies = queued
+                    .into_iter()
+                    .map(|command| self.execute(ctx, command))
+                    .collect();
+                RespType::Array(replies)
+            }
+            (_, Some(queued)) => {
+                queued.push(command);
+                RespType::SimpleString("QUEUED".into())
+            }
+            (_, None) => self.execute(ctx, command),
+        }
+    }
+
+    fn disconnected(&mut self, ctx: &Context) {
+        self.clients.remove(&ctx.id());
+    }
+}
+
+impl Store {
+    fn execute(&mut self, ctx: &mut Context, command: Vec<Vec<u8>>) -> RespType {
+        self.run(ctx, &command).unwrap_or_else(|error| error)
+    }
+
+    // `is_multiple_of` needs a newer toolchain than the rest of the crate
+    #[allow(clippy::manual_is_multiple_of)]
+    fn run(&mut self, ctx: &mut Context, command: &[Vec<u8>]) -> Reply {
+        let protocol = self.client(ctx).protocol;
+        let args = &command[1..];
+        match command[0].as_slice() {
+            b"PING" => Ok(match args.first() {
+                Some(message) => bulk(message),
+                None => status("PONG"),
+            }),
+            b"ECHO" => Ok(bulk(&args[0])),
+            b"HELLO" => self.hello(ctx, args),
+            b"AUTH" => Ok(ok()),
+            b"SELECT" => match integer(&args[0])? {
+                0 => Ok(ok()),
+                _ => Err(error("ERR DB index is out of range".into())),
+            },
+            b"CLIENT" => self.client_command(ctx, args),
+            b"QUIT" => {
+                ctx.close();
+                Ok(ok())
+            }
+
+            b"DEL" => Ok(count(args.iter().filter(|key| self.remove(key)))),
+            b"EXISTS" => Ok(count(args.iter().filter(|key| self.entry(key).is_some()))),
+            b"EXPIRE" => self.expire(&args[0], integer(&args[1])?.saturating_mul(1000)),
+            b"PEXPIRE" => self.expire(&args[0], integer(&args[1])?),
+            b"TTL" => Ok(RespType::Integer(match self.ttl(&args[0]) {
+                Some(ttl) if ttl >= 0 => (ttl + 500) / 1000,
+                Some(ttl) => ttl,
+                None => -2,
+            })),
+            b"PTTL" => Ok(RespType::Integer(self.ttl(&args[0]).unwrap_or(-2))),
+            b"PERSIST" => Ok(flag(
+                self.entry(&args[0])
+                    .is_some_and(|entry| entry.expires.take().is_some()),
+            )),
+            b"TYPE" => Ok(status(
+                self.entry(&args[0])
+                    .map_or("none", |entry| entry.value.type_name()),
+            )),
+            b"DBSIZE" => {
+                self.keys.retain(|_, entry| !entry.expired());
+                Ok(RespType::Integer(self.keys.len() as i64))
+            }
+            b"FLUSHDB" | b"FLUSHALL" => {
+                self.keys.clear();
+                Ok(ok())
+            }
+
+            b"GET" => Ok(match self.get(&args[0], Value::as_string)? {
+                Some(string) => bulk(string),
+                None => null(protocol),
+            }),
+            b"SET" => self.set(args, protocol),
+            b"MGET" => Ok(RespType::Array(
+                args.iter()
+                    .map(|key| match self.get(key, Value::as_string) {
+                        Ok(Some(string)) => bulk(string),
+                        _ => null(protocol),
+                    })
+                    .collect(),
+            )),
+            b"MSET" if args.len() % 2 != 0 => Err(wrong_arity(&command[0])),
+            b"MSET" => {
+                for pair in args.chunks_exact(2) {
+                    self.insert(&pair[0], Value::String(pair[1].clone()));
+                }
+                Ok(ok())
+            }
+            b"INCR" => self.increment(&args[0], 1),
+            b"DECR" => self.increment(&args[0], -1),
+            b"INCRBY" => self.increment(&args[0], integer(&args[1])?),
+            b"DECRBY" => match integer(&args[1])?.checked_neg() {
+                Some(decrement) => self.increment(&args[0], decrement),
+                None => Err(error("ERR decrement would overflow".into())),
+            },
+            b"APPEND" => {
+                let string =
+                    self.get_or_insert(&args[0], Value::String(Vec::new()), Value::as_string)?;
+                string.extend_from_slice(&args[1]);
+                Ok(length(string.len()))
+            }
+            b"STRLEN" => Ok(length(
+                self.get(&args[0], Value::as_string)?.map_or(0, |s| s.len()),
+            )),
+
+            b"HSET" if args.len() % 2 == 0 => Err(wrong_arity(&command[0])),
+            b"HSET" => {
+                let hash = self.get_or_insert(&args[0], Value::Hash(Vec::new()), Value::as_hash)?;
+                let mut added = 0;
+                for pair in args[1..].chunks_exact(2) {
+                    match hash.iter_mut().find(|(field, _)| *field == pair[0]) {
+                        Some((_, value)) => *value = pair[1].clone(),
+                        None => {
+                            hash.push((pair[0].clone(), pair[1].clone()));
+                            added += 1;
+                        }
+                    }
+                }
+                Ok(RespType::Integer(added))
+            }
+            b"HGET" => Ok(match self.hash_field(&args[0], &args[1])? {
+                Some(value) => bulk(&value),
+                None => null(protocol),
+            }),
+            b"HMGET" => {
+                let hash = self.get(&args[0], Value::as_hash)?;
+                Ok(RespType::Array(
+                    args[1..]
+                        .iter()
+                        .map(
+                            |field| match hash.as_ref().and_then(|hash| lookup(hash, field)) {
+                                Some(value) => bulk(value),
+                                None => null(protocol),
+                            },
+                        )
+                        .collect(),
+                ))
+            }
+            b"HDEL" => {
+                let removed = match self.get(&args[0], Value::as_hash)? {
+                    Some(hash) => {
+                        let before = hash.len();
+                        hash.retain(|(field, _)| !args[1..].contains(field));
+                        before - hash.len()
+                    }
+                    None => 0,
+                };
+                self.remove_if_empty(&args[0]);
+                Ok(length(removed))
+            }
+            b"HGETALL" => {
+                let hash = self.get(&args[0], Value::as_hash)?;
+                let pairs = hash
+                    .map(|hash| {
+                        hash.iter()
+                            .map(|(field, value)| (bulk(field), bulk(value)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Ok(map(protocol, pairs))
+            }
+            b"HEXISTS" => Ok(flag(self.hash_field(&args[0], &args[1])?.is_some())),
+            b"HLEN" => Ok(length(
+                self.get(&args[0], Value::as_hash)?.map_or(0, |h| h.len()),
+            )),
+            b"HKEYS" | b"HVALS" => {
+                let keys = command[0] == b"HKEYS";
+                let hash = self.get(&args[0], Value::as_hash)?;
+                Ok(RespType::Array(
+                    hash.into_iter()
+                        .flatten()
+                        .map(|(field, value)| bulk(if keys { field } else { value }))
+                        .collect(),
+                ))
+            }
+            b"HINCRBY" => {
+                let increment = integer(&args[2])?;
+                let hash = self.get_or_insert(&args[0], Value::Hash(Vec::new()), Value::as_hash)?;
+                let index = match hash.iter().position(|(field, _)| *field == args[1]) {
+                    Some(index) => index,
+                    None => {
+                        hash.push((args[1].clone(), b"0".to_vec()));
+                        hash.len() - 1
+                    }
+                };
+                let value = &mut hash[index].1;
+                let current =
+                    integer(value).map_err(|_| error("ERR hash value is not an integer".into()))?;
+                let result = current
+                    .checked_add(increment)
+                    .ok_or_else(|| error("ERR increment or decrement would overflow".into()))?;
+                *value = result.to_string().into_bytes();
+                Ok(RespType::Integer(result))
+            }
+
+            b"LPUSH" | b"RPUSH" => {
+                let front = command[0] == b"LPUSH";
+                let list =
+                    self.get_or_insert(&args[0], Value::List(VecDeque::new()), Value::as_list)?;
+                for value in &args[1..] {
+                    match front {
+                        true => list.push_front(value.clone()),
+                        false => list.push_back(value.clone()),
+                    }
+                }
+                Ok(length(list.len()))
+            }
+            b"LPOP" | b"RPOP" => self.pop(command, protocol),
+            b"LLEN" => Ok(length(
+                self.get(&args[0], Value::as_list)?.map_or(0, |l| l.len()),
+            )),
+            b"LRANGE" => {
+                let (start, stop) = (integer(&args[1])?, integer(&args[2])?);
+                let list = self.get(&args[0], Value::as_list)?;
+                let elements = list
+                    .map(|list| {
+                        let range = range(start, stop, list.len());
+                        list.range(range).map(|value| bulk(value)).collect()
+                    })
+                    .unwrap_or_default();
+                Ok(RespType::Array(elements))
+            }
+            b"LINDEX" => {
+                let index = integer(&args[1])?;
+                let list = self.get(&args[0], Value::as_list)?;
+                let value = list.and_then(|list| {
+                    let index = match index < 0 {
+                        true => list.len().checked_sub(index.unsigned_abs() as usize)?,
+                        false => index as usize,
+                    };
+                    list.get(index)
+                });
+                Ok(value.map_or_else(|| null(protocol), |value| bulk(value)))
+            }
+
+            b"SADD" => {
+                let set = self.get_or_insert(&args[0], Value::Set(Vec::new()), Value::as_set)?;
+                let mut added = 0;
+                for member in &args[1..] {
+                    if !set.contains(member) {
+                        set.push(member.clone());
+                        added += 1;
+                    }
+                }
+                Ok(RespType::Integer(added))
+            }
+            b"SREM" => {
+                let removed = match self.get(&args[0], Value::as_set)? {
+                    Some(set) => {
+                        let before = set.len();
+                        set.retain(|member| !args[1..].contains(member));
+                        before - set.len()
+                    }
+                    None => 0,
+                };
+                self.remove_if_empty(&args[0]);
+                Ok(length(removed))
+            }
+            b"SMEMBERS" => {
+                let set = self.get(&args[0], Value::as_set)?;
+                let members = set
+                    .map(|set| set.iter().map(|member| bulk(member)).collect())
+                    .unwrap_or_default();
+                Ok(match protocol {
+                    Protocol::Resp2 => RespType::Array(members),
+                    Protocol::Resp3 => RespType::Set(members),
+                })
+            }
+            b"SISMEMBER" => Ok(flag(
+                self.get(&args[0], Value::as_set)?
+                    .is_some_and(|set| set.contains(&args[1])),
+            )),
+            b"SCARD" => Ok(length(
+                self.get(&args[0], Value::as_set)?.map_or(0, |s| s.len()),
+            )),
+
+            b"SUBSCRIBE" => self.subscribe(ctx, args),
+            b"UNSUBSCRIBE" => self.unsubscribe(ctx, args),
+            b"PUBLISH" => {
+                let mut receivers = 0;
+                for client in self.clients.values() {
+                    if client.channels.contains(&args[0]) {
+                        let message = push(
+                            client.protocol,
+                            vec![bulk(b"message"), bulk(&args[0]), bulk(&args[1])],
+                        );
+                        // Only buffered for the client, so one that does not
+                        // read holds nobody up. A client that went away is
+                        // cleaned up when its connection closes.
+                        if client.writer.send(&message).is_ok() {
+                            receivers += 1;
+                        }
+                    }
+                }
+                Ok(RespType::Integer(receivers))
+            }
+            // Only reached for a name listed in COMMANDS without an arm here
+            _ => Err(error(format!(
+                "ERR unknown command '{}'",
+                lowercase(&command[0])
+            ))),
+        }
+    }
+
+    fn client(&mut self, ctx: &Context) -> &mut Client {
+        self.clients
+            .get_mut(&ctx.id())
+            .expect("client was registered")
+    }
+
+    fn hello(&mut self, ctx: &Context, args: &[Vec<u8>]) -> Reply {
+        let mut protocol = self.client(ctx).protocol;
+        if let Some(version) = args.first() {
+            protocol = match integer(version) {
+                Ok(2) => Protocol::Resp2,
+                Ok(3) => Protocol::Resp3,
+                Ok(_) => return Err(error("NOPROTO unsupported protocol version".into())),
+                Err(_) => {
+                    return Err(error(
+                        "ERR Protocol version is not an integer or out of range".into(),
+                    ))
+                }
+            };
+        }
+        let mut name = None;
+        let mut options = args.iter().skip(1);
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"AUTH" if options.len() >= 2 => {
+                    options.nth(1);
+                }
+                b"SETNAME" if options.len() >= 1 => name = options.next().cloned(),
+                _ => return Err(error("ERR syntax error".into())),
+            }
+        }
+
+        let client = self.client(ctx);
+        client.protocol = protocol;
+        if name.is_some() {
+            client.name = name;
+        }
+        let fields = [
+            ("server", bulk(b"redis")),
+            ("version", bulk(b"7.2.0")),
+            ("proto", RespType::Integer(protocol as i64)),
+            ("id", RespType::Integer(ctx.id() as i64)),
+            ("mode", bulk(b"standalone")),
+            ("role", bulk(b"master")),
+            ("modules", RespType::Array(Vec::new())),
+        ];
+        let pairs = fields
+            .into_iter()
+            .map(|(field, value)| (bulk(field.as_bytes()), value))
+            .collect();
+        Ok(map(protocol, pairs))
+    }
+
+    fn client_command(&mut self, ctx: &Context, args: &[Vec<u8>]) -> Reply {
+        let client = self.client(ctx);
+        match (args[0].to_ascii_uppercase().as_slice(), &args[1..]) {
+            (b"ID", []) => Ok(RespType::Integer(ctx.id() as i64)),
+            (b"SETNAME", [name]) => {
+                client.name = Some(name.clone());
+                Ok(ok())
+            }
+            (b"GETNAME", []) => Ok(match &client.name {
+                Some(name) => bulk(name),
+                None => null(client.protocol),
+            }),
+            (subcommand, _) => Err(error(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'",
+                lowercase(subcommand)
+            ))),
+        }
+    }
+
+    /// Looks up a key, removing it first if it has expired
+    fn entry(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        if self.keys.get(key).is_some_and(Entry::expired) {
+            self.keys.remove(key);
+        }
+        self.keys.get_mut(key)
+    }
+
+    /// Looks up a key holding the type picked out by `pick`
+    fn get<T>(
+        &mut self,
+        key: &[u8],
+        pick: fn(&mut Value) -> Option<&mut T>,
+    ) -> Result<Option<&mut T>, RespType> {
+        match self.entry(key) {
+            Some(entry) => match pick(&mut entry.value) {
+                Some(value) => Ok(Some(value)),
+                None => Err(error(WRONG_TYPE.into())),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`Store::get`], storing `empty` first if the key is missing
+    fn get_or_insert<T>(
+        &mut self,
+        key: &[u8],
+        empty: Value,
+        pick: fn(&mut Value) -> Option<&mut T>,
+    ) -> Result<&mut T, RespType> {
+        if self.entry(key).is_none() {
+            self.insert(key, empty);
+        }
+        self.get(key, pick)
+            .map(|value| value.expect("key was inserted"))
+    }
+
+    fn insert(&mut self, key: &[u8], value: Value) {
+        let entry = Entry {
+            value,
+            expires: None,
+        };
+        self.keys.insert(key.to_vec(), entry);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> bool {
+        self.entry(key).is_some() && self.keys.remove(key).is_some()
+    }
+
+    /// Deletes a key once its hash, list or set has no elements left
+    fn remove_if_empty(&mut self, key: &[u8]) {
+        if self
+            .keys
+            .get(key)
+            .is_some_and(|entry| entry.value.is_empty())
+        {
+            self.keys.remove(key);
+        }
+    }
+
+    fn hash_field(&mut self, key: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>, RespType> {
+        let hash = self.get(key, Value::as_hash)?;
+        Ok(hash.and_then(|hash| lookup(hash, field)).cloned())
+    }
+
+    fn expire(&mut self, key: &[u8], millis: i64) -> Reply {
+        if millis <= 0 {
+            return Ok(flag(self.remove(key)));
+        }
+        let expires = Instant::now()
+            .checked_add(Duration::from_millis(millis as u64))
+            .ok_or_else(|| error("ERR invalid expire time in 'expire' command".into()))?;
+        Ok(flag(
+            self.entry(key)
+                .map(|entry| entry.expires = Some(expires))
+                .is_some(),
+        ))
+    }
+
+    /// Milliseconds left before the key expires, -1 if it never does
+    fn ttl(&mut self, key: &[u8]) -> Option<i64> {
+        let entry = self.entry(key)?;
+        Some(match entry.expires {
+            Some(expires) => {
+                let left = expires.saturating_duration_since(Instant::now());
+                left.as_millis() as i64
+            }
+            None => -1,
+        })
+    }
+
+    fn set(&mut self, args: &[Vec<u8>], protocol: Protocol) -> Reply {
+        let (key, value) = (&args[0], &args[1]);
+        let mut expires = None;
+        let mut only_if = None;
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                unit @ (b"EX" | b"PX") if expires.is_none() => {
+                    let amount = integer(options.next().ok_or_else(syntax_error)?)?;
+                    let amount = match unit {
+                        b"EX" => Duration::from_secs(amount.max(0) as u64),
+                        _ => Duration::from_millis(amount.max(0) as u64),
+                    };
+                    expires = match amount.is_zero() {
+                        true => None,
+                        false => Instant::now().checked_add(amount),
+                    };
+                    if expires.is_none() {
+                        return Err(error("ERR invalid expire time in 'set' command".into()));
+                    }
+                }
+                condition @ (b"NX" | b"XX") if only_if.is_none() => {
+                    only_if = Some(condition == b"XX");
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        if only_if.is_some_and(|exists| exists != self.entry(key).is_some()) {
+            return Ok(null(protocol));
+        }
+        self.keys.insert(
+            key.clone(),
+            Entry {
+                value: Value::String(value.clone()),
+                expires,
+            },
+        );
+        Ok(ok())
+    }
+
+    /// Adds to the integer held by a key, keeping its expiry
+    fn increment(&mut self, key: &[u8], increment: i64) -> Reply {
+        let current = match self.get(key, Value::as_string)? {
+            Some(string) => integer(string)?,
+            None => 0,
+        };
+        let result = current
+            .checked_add(increment)
+            .ok_or_else(|| error("ERR increment or decrement would overflow".into()))?;
+        *self.get_or_insert(key, Value::String(Vec::new()), Value::as_string)? =
+            result.to_string().into_bytes();
+        Ok(RespType::Integer(result))
+    }
+
+    fn pop(&mut self, command: &[Vec<u8>], protocol: Protocol) -> Reply {
+        let front = command[0] == b"LPOP";
+        let key = &command[1];
+        if command.len() > 3 {
+            return Err(wrong_arity(&command[0]));
+        }
+        let count = match command.get(2).map(|count| integer(count)).transpose()? {
+            Some(count) if count < 0 => {
+                return Err(error("ERR value is out of range, must be positive".into()))
+            }
+            count => count.map(|count| count as usize),
+        };
+
+        let Some(list) = self.get(key, Value::as_list)? else {
+            return Ok(match (count, protocol) {
+                (Some(_), Protocol::Resp2) => RespType::NullArray,
+                _ => null(protocol),
+            });
+        };
+        let mut popped = Vec::new();
+        for _ in 0..count.unwrap_or(1) {
+            let value = match front {
+                true => list.pop_front(),
+                false => list.pop_back(),
+            };
+            match value {
+                Some(value) => popped.push(RespType::BulkString(value)),
+                None => break,
+            }
+        }
+        self.remove_if_empty(key);
+        Ok(match count {
+            Some(_) => RespType::Array(popped),
+            None => popped.pop().unwrap_or_else(|| null(protocol)),
+        })
+    }
+
+    /// Subscribes to each channel, sending a confirmation for each. The last
+    /// one is the reply, the ones before are sent ahead of it.
+    fn subscribe(&mut self, ctx: &Context, channels: &[Vec<u8>]) -> Reply {
+        let client = self.client(ctx);
+        let mut confirmations = Vec::new();
+        for channel in channels {
+            if !client.channels.contains(channel) {
+                client.channels.push(channel.clone());
+            }
+            confirmations.push(push(
+                client.protocol,
+                vec![
+                    bulk(b"subscribe"),
+                    bulk(channel),
+                    length(client.channels.len()),
+                ],
+            ));
+        }
+        client.confirm(confirmations)
+    }
+
+    /// Unsubscribes from the given channels, or from all of them when none
+    /// are given
+    fn unsubscribe(&mut self, ctx: &Context, channels: &[Vec<u8>]) -> Reply {
+        let client = self.client(ctx);
+        let channels = match channels.is_empty() {
+            true => client.channels.clone(),
+            false => channels.to_vec(),
+        };
+        let protocol = client.protocol;
+        if channels.is_empty() {
+            let frame = vec![bulk(b"unsubscribe"), null(protocol), length(0)];
+            return Ok(push(protocol, frame));
+        }
+        let mut confirmations = Vec::new();
+        for channel in channels {
+            client.channels.retain(|subscribed| *subscribed != channel);
+            confirmations.push(push(
+                protocol,
+                vec![
+                    bulk(b"unsubscribe"),
+                    RespType::BulkString(channel),
+                    length(client.channels.len()),
+                ],
+            ));
+        }
+        client.confirm(confirmations)
+    }
+}
+
+impl Client {
+    /// Sends all but the last confirmation, which is returned as the reply
+    fn confirm(&self, mut confirmations: Vec<RespType>) -> Reply {
+        let last = confirmations.pop().expect("at least one confirmation");
+        for confirmation in &confirmations {
+            // Buffered ahead of the reply, a broken connection is cleaned up
+            // once it closes
+            let _ = self.writer.send(confirmation);
+        }
+        Ok(last)
+    }
+}
+
+fn check_arity(command: &[Vec<u8>]) -> Result<(), RespType> {
+    let name = &command[0];
+    let arity = COMMANDS
+        .iter()
+        .find(|(known, _)| known.as_bytes() == name.as_slice())
+        .map(|(_, arity)| *arity);
+    let args = command.len() as i32;
+    match arity {
+        None => Err(error(format!("ERR unknown command '{}'", lowercase(name)))),
+        Some(arity) if arity >= 0 && args != arity => Err(wrong_arity(name)),
+        Some(arity) if arity < 0 && args < -arity => Err(wrong_arity(name)),
+        Some(_) => Ok(()),
+    }
+}
+
+/// The part of a list covered by inclusive `start` and `stop` indexes, which
+/// count from the end when negative
+fn range(start: i64, stop: i64, len: usize) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let resolve = |index: i64| match index < 0 {
+        true => (len + index).max(0),
+        false => index,
+    };
+    let (start, stop) = (resolve(start), resolve(stop).min(len - 1));
+    match start > stop {
+        true => 0..0,
+        false => start as usize..stop as usize + 1,
+    }
+}
+
+fn lookup<'a>(hash: &'a [(Vec<u8>, Vec<u8>)], field: &[u8]) -> Option<&'a Vec<u8>> {
+    hash.iter()
+        .find(|(known, _)| known.as_slice() == field)
+        .map(|(_, value)| value)
+}
+
+fn integer(arg: &[u8]) -> Result<i64, RespType> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| error(NOT_INTEGER.into()))
+}
+
+fn lowercase(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).to_lowercase()
+}
+
+fn ok() -> RespType {
+    status("OK")
+}
+
+fn status(status: &str) -> RespType {
+    RespType::SimpleString(status.into())
+}
+
+fn error(message: String) -> RespType {
+    RespType::Error(message)
+}
+
+fn syntax_error() -> RespType {
+    error("ERR syntax error".into())
+}
+
+fn wrong_arity(name: &[u8]) -> RespType {
+    error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        lowercase(name)
+    ))
+}
+
+fn bulk(data: &[u8]) -> RespType {
+    RespType::BulkString(data.to_vec())
+}
+
+fn length(len: usize) -> RespType {
+    RespType::Integer(len as i64)
+}
+
+fn count<I: Iterator>(items: I) -> RespType {
+    length(items.count())
+}
+
+fn flag(flag: bool) -> RespType {
+    RespType::Integer(flag as i64)
+}
+
+fn null(protocol: Protocol) -> RespType {
+    match protocol {
+        Protocol::Resp2 => RespType::Null,
+        Protocol::Resp3 => RespType::Nil,
+    }
+}
+
+fn map(protocol: Protocol, pairs: Vec<(RespType, RespType)>) -> RespType {
+    match protocol {
+        Protocol::Resp2 => RespType::Array(
+            pairs
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+        ),
+        Protocol::Resp3 => RespType::Map(pairs),
+    }
+}
+
+fn push(protocol: Protocol, elements: Vec<RespType>) -> RespType {
+    match protocol {
+        Protocol::Resp2 => RespType::Array(elements),
+        Protocol::Resp3 => RespType::Push(elements),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientError, ErrorCode, Pipeline, Subscriber, Transaction, TransactionOutcome};
+    use std::io::{Read, Write};
+    use RespType::*;
+
+    fn command(line: &str) -> Vec<Vec<u8>> {
+        line.split(' ').map(|arg| arg.as_bytes().to_vec()).collect()
+    }
+
+    fn bulk(data: &str) -> RespType {
+        BulkString(data.into())
+    }
+
+    fn connect(redis: &FakeRedis, protocol: Option<Protocol>) -> Connection {
+        let config = ConnectionConfig {
+            protocol,
+            ..Default::default()
+        };
+        redis.connect(&config).unwrap()
+    }
+
+    fn error_code(result: ClientResult<RespType>) -> ErrorCode {
+        match result {
+            Err(ClientError::ReplyError(error)) => error.code,
+            other => panic!("expected an error reply, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strings() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        assert_eq!(conn.command(command("GET a"))?, Null);
+        conn.command_ok(command("SET a 1"))?;
+        assert_eq!(conn.command(command("INCRBY a 9"))?, Integer(10));
+        assert_eq!(conn.command(command("DECR a"))?, Integer(9));
+        assert_eq!(conn.command(command("APPEND a 0"))?, Integer(2));
+        assert_eq!(conn.command(command("GET a"))?, bulk("90"));
+        assert_eq!(conn.command(command("SET a 1 NX"))?, Null);
+        assert_eq!(conn.command(command("SET b 1 XX"))?, Null);
+        conn.command_ok(command("MSET b 2 c 3"))?;
+        assert_eq!(
+            conn.command(command("MGET a b x"))?,
+            Array(vec![bulk("90"), bulk("2"), Null])
+        );
+        assert_eq!(conn.command(command("DEL a b x"))?, Integer(2));
+        assert_eq!(conn.command(command("EXISTS a c"))?, Integer(1));
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        conn.command(command("RPUSH list a"))?;
+        assert_eq!(
+            error_code(conn.command(command("GET list"))),
+            ErrorCode::WrongType
+        );
+        conn.command_ok(command("SET a b"))?;
+        assert_eq!(
+            error_code(conn.command(command("INCR a"))),
+            ErrorCode::Other("ERR".into())
+        );
+        match conn.command(command("GET")) {
+            Err(ClientError::ReplyError(error)) => {
+                assert_eq!(error.message, "wrong number of arguments for 'get' command")
+            }
+            other => panic!("expected an arity error, got {:?}", other),
+        }
+        assert!(conn.command(command("NOPE")).is_err());
+        // The connection is still usable after errors
+        assert_eq!(conn.command(command("PING"))?, SimpleString("PONG".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn hashes() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        assert_eq!(conn.command(command("HSET h a 1 b 2"))?, Integer(2));
+        assert_eq!(conn.command(command("HSET h a 3"))?, Integer(0));
+        assert_eq!(conn.command(command("HINCRBY h b 5"))?, Integer(7));
+        assert_eq!(conn.command(command("HGET h a"))?, bulk("3"));
+        assert_eq!(
+            conn.command(command("HMGET h a x"))?,
+            Array(vec![bulk("3"), Null])
+        );
+        assert_eq!(
+            conn.command(command("HGETALL h"))?,
+            Array(vec![bulk("a"), bulk("3"), bulk("b"), bulk("7")])
+        );
+
+        // Maps are only sent once RESP3 is negotiated
+        let mut conn = connect(&redis, Some(Protocol::Resp3));
+        assert_eq!(
+            conn.command(command("HGETALL h"))?,
+            Map(vec![(bulk("a"), bulk("3")), (bulk("b"), bulk("7"))])
+        );
+        assert_eq!(conn.command(command("HGET h x"))?, Nil);
+        assert_eq!(conn.command(command("HDEL h a b"))?, Integer(2));
+        assert_eq!(conn.command(command("EXISTS h"))?, Integer(0));
+        Ok(())
+    }
+
+    #[test]
+    fn lists() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        assert_eq!(conn.command(command("RPUSH l b c d"))?, Integer(3));
+        assert_eq!(conn.command(command("LPUSH l a"))?, Integer(4));
+        assert_eq!(
+            conn.command(command("LRANGE l 1 -2"))?,
+            Array(vec![bulk("b"), bulk("c")])
+        );
+        assert_eq!(conn.command(command("LINDEX l -1"))?, bulk("d"));
+        assert_eq!(conn.command(command("LPOP l"))?, bulk("a"));
+        assert_eq!(
+            conn.command(command("RPOP l 5"))?,
+            Array(vec![bulk("d"), bulk("c"), bulk("b")])
+        );
+        assert_eq!(conn.command(command("LLEN l"))?, Integer(0));
+        assert_eq!(conn.command(command("RPOP l 1"))?, NullArray);
+        Ok(())
+    }
+
+    #[test]
+    fn sets() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        assert_eq!(conn.command(command("SADD s a b a"))?, Integer(2));
+        assert_eq!(conn.command(command("SISMEMBER s b"))?, Integer(1));
+        assert_eq!(conn.command(command("SREM s b x"))?, Integer(1));
+        assert_eq!(conn.command(command("SCARD s"))?, Integer(1));
+        assert_eq!(conn.command(command("TYPE s"))?, SimpleString("set".into()));
+        assert_eq!(conn.command(command("SMEMBERS s"))?, Array(vec![bulk("a")]));
+
+        let mut conn = connect(&redis, Some(Protocol::Resp3));
+        assert_eq!(conn.command(command("SMEMBERS s"))?, Set(vec![bulk("a")]));
+        Ok(())
+    }
+
+    #[test]
+    fn expiry() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        conn.command_ok(command("SET a 1 EX 100"))?;
+        assert_eq!(conn.command(command("TTL a"))?, Integer(100));
+        assert_eq!(conn.command(command("PERSIST a"))?, Integer(1));
+        assert_eq!(conn.command(command("TTL a"))?, Integer(-1));
+        assert_eq!(conn.command(command("TTL x"))?, Integer(-2));
+
+        assert_eq!(conn.command(command("PEXPIRE a 20"))?, Integer(1));
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(conn.command(command("GET a"))?, Null);
+        assert_eq!(conn.command(command("PTTL a"))?, Integer(-2));
+        assert_eq!(conn.command(command("EXPIRE a 10"))?, Integer(0));
+        Ok(())
+    }
+
+    #[test]
+    fn transactions() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        let mut transaction = Transaction::new();
+        transaction
+            .command(command("SET a 1"))
+            .command(command("INCR a"))
+            .command(command("HGET a b"));
+        match conn.transaction(&transaction)? {
+            TransactionOutcome::Committed(results) => {
+                assert_eq!(results.len(), 3);
+                assert_eq!(results[1].as_ref().unwrap(), &Integer(2));
+                assert!(results[2].is_err());
+            }
+            TransactionOutcome::Aborted => panic!("transaction was aborted"),
+        }
+
+        conn.command_ok(command("MULTI"))?;
+        assert!(conn.command(command("NOPE")).is_err());
+        assert_eq!(
+            error_code(conn.command(command("EXEC"))),
+            ErrorCode::Other("EXECABORT".into())
+        );
+        assert_eq!(conn.command(command("GET a"))?, bulk("2"));
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut conn = connect(&redis, None);
+        let mut pipeline = Pipeline::new();
+        pipeline
+            .command(command("SET a 1"))
+            .command(command("INCR a"))
+            .command(command("GET a"));
+        let replies: Vec<RespType> = conn
+            .pipeline(&pipeline)?
+            .into_iter()
+            .collect::<ClientResult<_>>()?;
+        assert_eq!(
+            replies,
+            vec![SimpleString("OK".into()), Integer(2), bulk("2")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pubsub() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        for protocol in [None, Some(Protocol::Resp3)] {
+            let mut subscriber = Subscriber::new(connect(&redis, protocol));
+            subscriber.subscribe(command("news sport"))?;
+            assert_eq!(subscriber.subscriptions(), 2);
+
+            let mut publisher = connect(&redis, None);
+            assert_eq!(publisher.command(command("PUBLISH news hi"))?, Integer(1));
+            assert_eq!(publisher.command(command("PUBLISH other hi"))?, Integer(0));
+            let message = subscriber.next_message()?;
+            assert_eq!(message.channel, b"news");
+            assert_eq!(message.payload, b"hi");
+
+            subscriber.unsubscribe(Vec::new())?;
+            assert_eq!(subscriber.subscriptions(), 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn slow_subscriber() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut subscriber = Subscriber::new(connect(&redis, None));
+        subscriber.subscribe(command("news"))?;
+
+        // Far more than the socket buffers hold, while the subscriber is not
+        // reading
+        let mut publisher = connect(&redis, None);
+        let payload = vec![b'x'; 1024];
+        for _ in 0..4096 {
+            let reply =
+                publisher.command(vec!["PUBLISH".into(), "news".into(), payload.clone()])?;
+            assert_eq!(reply, Integer(1));
+        }
+        assert_eq!(
+            connect(&redis, None).command(command("PING"))?,
+            SimpleString("PONG".into())
+        );
+        for _ in 0..4096 {
+            assert_eq!(subscriber.next_message()?.payload, payload);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn hello() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let config = ConnectionConfig {
+            protocol: Some(Protocol::Resp3),
+            client_name: Some("app".into()),
+            ..Default::default()
+        };
+        let mut conn = redis.connect(&config)?;
+        let info = conn.server_info().unwrap();
+        assert_eq!(info.proto, Protocol::Resp3);
+        assert_eq!(info.server, "redis");
+        let id = info.id;
+        assert_eq!(conn.command(command("CLIENT ID"))?, Integer(id));
+        assert_eq!(conn.command(command("CLIENT GETNAME"))?, bulk("app"));
+        assert_eq!(
+            error_code(conn.command(command("HELLO 4"))),
+            ErrorCode::NoProto
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn inline() -> ClientResult<()> {
+        let redis = FakeRedis::start()?;
+        let mut stream = TcpStream::connect(redis.addr())?;
+        stream.write_all(b"SET greeting \"hello world\"\r\nGET greeting\r\nQUIT\r\n")?;
+        let mut replies = Vec::new();
+        stream.read_to_end(&mut replies)?;
+        assert_eq!(replies, b"+OK\r\n$11\r\nhello world\r\n+OK\r\n");
+        Ok(())
+    }
+}
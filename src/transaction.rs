@@ -0,0 +1,190 @@
+use super::connection::check_reply;
+use super::error::{ClientError, ClientResult};
+use super::Pipeline;
+use super::RespType;
+
+/// Collects commands to run atomically between `MULTI` and `EXEC`.
+///
+/// Keys can be watched beforehand by sending `WATCH` on the same connection,
+/// if any of them change the transaction comes back as
+/// [`TransactionOutcome::Aborted`].
+#[derive(Debug, Default)]
+pub struct Transaction {
+    commands: Vec<Vec<Vec<u8>>>,
+}
+
+/// How a transaction ended
+#[derive(Debug)]
+pub enum TransactionOutcome {
+    /// `EXEC` ran the commands, with a result for each in order. Commands can
+    /// still fail individually at run time.
+    Committed(Vec<ClientResult<RespType>>),
+    /// `EXEC` did not run anything, because a watched key was changed
+    Aborted,
+}
+
+impl Transaction {
+    /// Creates an empty transaction
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command, see [`RespType::command`]
+    pub fn command(&mut self, command: Vec<Vec<u8>>) -> &mut Self {
+        self.commands.push(command);
+        self
+    }
+
+    /// Number of commands in the transaction
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Checks if no commands were added
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Builds the pipeline to send, the commands wrapped in `MULTI` and `EXEC`
+    pub fn pipeline(&self) -> Pipeline {
+        let mut pipeline = Pipeline::new();
+        pipeline.command(vec!["MULTI".into()]);
+        for command in &self.commands {
+            pipeline.command(command.clone());
+        }
+        pipeline.command(vec!["EXEC".into()]);
+        pipeline
+    }
+
+    /// Unpacks the replies to [`Transaction::pipeline`]. The `MULTI` and
+    /// `QUEUED` acknowledgements are checked, then the `EXEC` reply is split
+    /// into a result per command. Fails with [`ClientError::ReplyCountError`]
+    /// if the number of replies does not match the pipeline.
+    pub fn unpack(&self, replies: Vec<ClientResult<RespType>>) -> ClientResult<TransactionOutcome> {
+        if replies.len() != self.len() + 2 {
+            return Err(ClientError::ReplyCountError {
+                expected: self.len() + 2,
+                actual: replies.len(),
+            });
+        }
+        let mut replies = replies.into_iter();
+
+        expect_status(replies.next(), "OK")?;
+        for _ in 0..self.len() {
+            expect_status(replies.next(), "QUEUED")?;
+        }
+
+        match replies.next().expect("reply count was checked")? {
+            RespType::Array(results) if results.len() == self.len() => {
+                let results = results.into_iter().map(check_reply).collect();
+                Ok(TransactionOutcome::Committed(results))
+            }
+            // RESP3 connections reply with a null instead of a null array
+            RespType::NullArray | RespType::Nil => Ok(TransactionOutcome::Aborted),
+            other => Err(ClientError::UnexpectedReplyError(other)),
+        }
+    }
+}
+
+fn expect_status(reply: Option<ClientResult<RespType>>, status: &str) -> ClientResult<()> {
+    match reply.expect("reply count was checked")? {
+        RespType::SimpleString(reply) if reply == status => Ok(()),
+        other => Err(ClientError::UnexpectedReplyError(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServerError;
+    use RespType::*;
+
+    fn transaction() -> Transaction {
+        let mut transaction = Transaction::new();
+        transaction
+            .command(vec!["SET".into(), "a".into(), "1".into()])
+            .command(vec!["INCR".into(), "a".into()]);
+        transaction
+    }
+
+    fn status(status: &str) -> ClientResult<RespType> {
+        Ok(SimpleString(status.into()))
+    }
+
+    #[test]
+    fn pipeline() {
+        let pipeline = transaction().pipeline();
+        assert_eq!(pipeline.len(), 4);
+        assert!(pipeline.encode().starts_with(b"*1\r\n$5\r\nMULTI\r\n"));
+        assert!(pipeline.encode().ends_with(b"*1\r\n$4\r\nEXEC\r\n"));
+    }
+
+    #[test]
+    fn committed() -> ClientResult<()> {
+        let exec = Array(vec![
+            SimpleString("OK".into()),
+            Error("ERR value is not an integer".into()),
+        ]);
+        let replies = vec![status("OK"), status("QUEUED"), status("QUEUED"), Ok(exec)];
+        match transaction().unpack(replies)? {
+            TransactionOutcome::Committed(results) => {
+                assert_eq!(results.len(), 2);
+                assert!(results[0].is_ok());
+                assert!(matches!(results[1], Err(ClientError::ReplyError(_))));
+            }
+            other => panic!("expected a committed transaction, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn aborted() -> ClientResult<()> {
+        for exec in [NullArray, Nil] {
+            let replies = vec![status("OK"), status("QUEUED"), status("QUEUED"), Ok(exec)];
+            let outcome = transaction().unpack(replies)?;
+            assert!(matches!(outcome, TransactionOutcome::Aborted));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn queue_error() {
+        let replies = vec![
+            status("OK"),
+            status("QUEUED"),
+            Err(ClientError::ReplyError(ServerError::parse(
+                "ERR unknown command",
+            ))),
+            Err(ClientError::ReplyError(ServerError::parse(
+                "EXECABORT Transaction discarded",
+            ))),
+        ];
+        match transaction().unpack(replies) {
+            Err(ClientError::ReplyError(error)) => {
+                assert_eq!(error.to_string(), "ERR unknown command")
+            }
+            other => panic!("expected the queueing error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn not_queued() {
+        let replies = vec![status("OK"), status("QUEUED"), status("OK"), Ok(NullArray)];
+        assert!(matches!(
+            transaction().unpack(replies),
+            Err(ClientError::UnexpectedReplyError(_))
+        ));
+    }
+
+    #[test]
+    fn reply_count() {
+        let replies = vec![status("OK"), status("QUEUED")];
+        assert!(matches!(
+            transaction().unpack(replies),
+            Err(ClientError::ReplyCountError {
+                expected: 4,
+                actual: 2
+            })
+        ));
+    }
+}